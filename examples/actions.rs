@@ -1,6 +1,5 @@
 use anyhow::bail;
 use embedded_svc::wifi::{ClientConfiguration, Configuration, Wifi};
-use esp_idf_hal::interrupt;
 use esp_idf_hal::modem::Modem;
 use esp_idf_svc::eventloop::EspSystemEventLoop;
 use esp_idf_svc::nvs::EspDefaultNvsPartition;
@@ -8,17 +7,12 @@ use esp_idf_svc::sntp::{self, SyncStatus};
 use esp_idf_svc::wifi::{EspWifi, WifiWait};
 use esp_idf_sys as _; // If using the `binstart` feature of `esp-idf-sys`, always keep this module imported
 
-use std::cell::RefCell;
-use std::sync::Mutex;
 use std::time::Duration;
 
-use bytebeam_esp_rs::{Action, ByteBeamClient};
-use esp_idf_hal::gpio::{Gpio2, Output, PinDriver};
+use bytebeam_esp_rs::{Action, ActionContext, ByteBeamClient};
+use esp_idf_hal::gpio::PinDriver;
 use esp_idf_hal::peripherals::Peripherals;
 
-static ONBOARD_LED: Mutex<RefCell<Option<PinDriver<Gpio2, Output>>>> =
-    Mutex::new(RefCell::new(None));
-
 #[toml_cfg::toml_config]
 pub struct Config {
     #[default("")]
@@ -44,36 +38,28 @@ fn main() -> anyhow::Result<()> {
     while sntp.get_sync_status() != SyncStatus::Completed {}
     println!("SNTP Initialized");
 
-    let pin2 = peripherals.pins.gpio2;
-    let pin2_driver = PinDriver::output(pin2)?;
-    interrupt::free(|| ONBOARD_LED.lock().unwrap().replace(Some(pin2_driver)));
+    // handler owns the driver, no need for globals
+    let mut onboard_led = PinDriver::output(peripherals.pins.gpio2)?;
 
     // Bytebeam!
     let bytebeam_client = ByteBeamClient::init()?;
 
-    bytebeam_client.register_action_handle("toggle".into(), &toggle);
+    bytebeam_client.register_action_handle(
+        "toggle".into(),
+        move |_action: Action, ctx: ActionContext| {
+            match onboard_led.toggle() {
+                Ok(_) => ctx.complete(),
+                Err(_) => ctx.fail(&["Failed to toggle LED"]),
+            }
+            .ok(); // just to satisfy clippy for now!
+        },
+    );
 
     loop {
         std::thread::sleep(Duration::from_secs(1));
     }
 }
 
-fn toggle(action: Action, bytebeam_client: &ByteBeamClient) {
-    let mut onboard_led = ONBOARD_LED.lock().unwrap();
-    let onboard_led = onboard_led.get_mut().as_mut().unwrap();
-
-    match onboard_led.toggle() {
-        Ok(_) => bytebeam_client.publish_action_status(&action.id, 100, "Toggled", None),
-        Err(_) => bytebeam_client.publish_action_status(
-            &action.id,
-            0,
-            "Failed",
-            Some(&["Failed to toggle LED"]),
-        ),
-    }
-    .ok(); // just to satisfy clippy for now!
-}
-
 fn connect_wifi(
     modem: Modem,
     sysloop: EspSystemEventLoop,
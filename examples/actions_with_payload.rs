@@ -1,6 +1,5 @@
 use anyhow::bail;
 use embedded_svc::wifi::{ClientConfiguration, Configuration, Wifi};
-use esp_idf_hal::interrupt;
 use esp_idf_hal::modem::Modem;
 use esp_idf_svc::eventloop::EspSystemEventLoop;
 use esp_idf_svc::nvs::EspDefaultNvsPartition;
@@ -9,17 +8,11 @@ use esp_idf_svc::wifi::{EspWifi, WifiWait};
 use esp_idf_sys as _;
 use serde::Deserialize; // If using the `binstart` feature of `esp-idf-sys`, always keep this module imported
 
-use std::cell::RefCell;
-use std::sync::Mutex;
 use std::time::Duration;
 
-use bytebeam_esp_rs::{Action, ByteBeamClient};
-use esp_idf_hal::gpio::{Gpio2, Output, PinDriver};
+use bytebeam_esp_rs::{ActionContext, ByteBeamClient};
 use esp_idf_hal::peripherals::Peripherals;
 
-static ONBOARD_LED: Mutex<RefCell<Option<PinDriver<Gpio2, Output>>>> =
-    Mutex::new(RefCell::new(None));
-
 #[derive(Deserialize)]
 struct MyPaylaod {
     status: String,
@@ -42,29 +35,15 @@ fn main() -> anyhow::Result<()> {
     while sntp.get_sync_status() != SyncStatus::Completed {}
     println!("SNTP Initialized");
 
-    let pin2 = peripherals.pins.gpio2;
-    let pin2_driver = PinDriver::output(pin2)?;
-    interrupt::free(|| ONBOARD_LED.lock().unwrap().replace(Some(pin2_driver)));
-
     // Bytebeam!
     let bytebeam_client = ByteBeamClient::init()?;
 
-    bytebeam_client.register_action_handle(
+    bytebeam_client.register_typed_action(
         "example_action".into(),
-        &|action: Action, bytebeam_client: &ByteBeamClient| {
-            if let Some(data) = action.payload {
-                let parsed = serde_json::from_str(&data);
-                if parsed.is_err() {
-                    eprintln!("Invalid payload for action {}", action.name);
-                    return;
-                }
-                let parsed: MyPaylaod = parsed.unwrap();
-                println!("TRIGERRING THE ACTION {}!!!", action.name);
-                println!("status: {}", parsed.status)
-            }
-            bytebeam_client
-                .publish_action_status(&action.id, 100, "Completed", None)
-                .expect("action status published");
+        |payload: MyPaylaod, ctx: ActionContext| {
+            println!("TRIGERRING THE ACTION {}!!!", ctx.id());
+            println!("status: {}", payload.status);
+            ctx.complete().expect("action status published");
         },
     );
 
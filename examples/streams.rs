@@ -1,24 +1,17 @@
 use anyhow::bail;
 use embedded_svc::wifi::{ClientConfiguration, Configuration, Wifi};
-use esp_idf_hal::interrupt;
 use esp_idf_hal::modem::Modem;
 use esp_idf_svc::eventloop::EspSystemEventLoop;
 use esp_idf_svc::nvs::EspDefaultNvsPartition;
 use esp_idf_svc::sntp::{self, SyncStatus};
 use esp_idf_svc::wifi::{EspWifi, WifiWait};
 
-use std::cell::RefCell;
-use std::sync::Mutex;
 use std::time::Duration;
 
 use bytebeam_esp_rs::ByteBeamClient;
-use esp_idf_hal::gpio::{Gpio2, Output, PinDriver};
 use esp_idf_hal::peripherals::Peripherals;
 use serde::Serialize;
 
-static ONBOARD_LED: Mutex<RefCell<Option<PinDriver<Gpio2, Output>>>> =
-    Mutex::new(RefCell::new(None));
-
 #[toml_cfg::toml_config]
 pub struct Config {
     #[default("")]
@@ -50,10 +43,6 @@ fn main() -> anyhow::Result<()> {
     while sntp.get_sync_status() != SyncStatus::Completed {}
     println!("SNTP Initialized");
 
-    let pin2 = peripherals.pins.gpio2;
-    let pin2_driver = PinDriver::output(pin2)?;
-    interrupt::free(|| ONBOARD_LED.lock().unwrap().replace(Some(pin2_driver)));
-
     // Bytebeam!
     let bytebeam_client = ByteBeamClient::init()?;
 
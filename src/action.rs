@@ -0,0 +1,1035 @@
+//! Execution of actions received from cloud
+use std::{
+    any::Any,
+    cmp::Reverse,
+    collections::VecDeque,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex, PoisonError,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::{error, info, warn};
+use serde::Serialize;
+
+use crate::{
+    error::Result,
+    protocol::{Action, CancelAction},
+    queue::DropPolicy,
+    transport::{DefaultTransport, Transport},
+    ByteBeamClient,
+};
+
+/// Name of action cloud sends for cancelling another action
+pub(crate) const CANCEL_ACTION: &str = "cancel_action";
+
+/// Name of stream to which names of registered actions are published on connect
+const SUPPORTED_ACTIONS: &str = "supported_actions";
+
+/// Interval at which deadlines of running actions are checked
+pub(crate) const TIMEOUT_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Flag set when cloud asks to cancel an action, available to handler as [`Action::cancellation`]
+///
+/// Handlers doing long running work should check it every now and then, and return early once it
+/// is set. Client reports the action as `"Cancelled"` once its [`ActionContext`] is dropped.
+///
+/// # Example
+/// ```no_run
+/// use bytebeam_esp_rs::{Action, ActionContext};
+///
+/// fn blink(action: Action, ctx: ActionContext) {
+///     for _ in 0..100 {
+///         if action.cancellation.is_cancelled() {
+///             return;
+///         }
+///         // toggle LED, sleep, ...
+///     }
+///     ctx.complete().ok();
+/// }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    /// Ask holders of token to stop
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed)
+    }
+
+    /// Whether action was cancelled
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Limits on execution of an action, set with [`ByteBeamClient::set_action_options`]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ActionOptions {
+    /// Time since handler started after which action is cancelled and reported as failed
+    pub timeout: Option<Duration>,
+    /// Time after `timeout` after which device is reset if handler still hasn't returned
+    ///
+    /// Meant for handlers which can hang without a way out, a hung handler otherwise keeps its
+    /// worker busy forever. On ESP-IDF, task running handler is handed to the task watchdog,
+    /// which resets device with a backtrace of it. On host, process is aborted instead. A
+    /// [`reset_hook`](crate::ByteBeamClientBuilder::reset_hook) is called instead of either.
+    pub reset_after: Option<Duration>,
+    /// Actions of higher priority are picked up by workers first, and push out queued actions
+    /// of lower priority when queue is full
+    pub priority: u8,
+}
+
+impl ActionOptions {
+    /// Priority of built-in actions which take over device, i.e. firmware updates and reboots,
+    /// so that they don't wait behind actions of application
+    pub const SYSTEM_PRIORITY: u8 = 200;
+}
+
+/// Action which is queued or running
+pub(crate) struct RunningAction {
+    cancellation: CancellationToken,
+    status: Arc<Mutex<StatusTracker>>,
+    options: ActionOptions,
+    /// Set once a worker starts running handler
+    started_at: Option<Instant>,
+    worker: Option<Worker>,
+    handler_returned: bool,
+    timed_out: bool,
+    /// Device reset was asked for because handler is hung
+    reset: bool,
+}
+
+impl RunningAction {
+    /// Whether `check_timeouts` still has something to do for action
+    fn has_deadline(&self) -> bool {
+        let (Some(_), Some(_)) = (self.started_at, self.options.timeout) else {
+            return false;
+        };
+        !self.timed_out
+            || (self.options.reset_after.is_some() && !self.handler_returned && !self.reset)
+    }
+}
+
+impl<T: Transport> ByteBeamClient<T> {
+    /// Hand `action` over to workers, cancellations are handled right away so that they don't
+    /// wait behind the action they are cancelling
+    pub(crate) fn dispatch_action(&self, action: Action, workers: &ActionQueue) {
+        if !self.journal.lock().unwrap().start(&action.id, &action.name) {
+            warn!(
+                "Skipping action {} ({}), it was received before",
+                action.id, action.name
+            );
+            return;
+        }
+
+        if action.name == CANCEL_ACTION {
+            self.cancel_action(&action);
+            self.journal.lock().unwrap().finish(&action.id);
+            return;
+        }
+
+        let running = RunningAction {
+            cancellation: action.cancellation.clone(),
+            status: Arc::default(),
+            options: ActionOptions::default(),
+            started_at: None,
+            worker: None,
+            handler_returned: false,
+            timed_out: false,
+            reset: false,
+        };
+        self.running_actions
+            .lock()
+            .unwrap()
+            .insert(action.id.clone(), running);
+
+        let priority = self
+            .action_options
+            .lock()
+            .unwrap()
+            .get(&action.name)
+            .map_or(0, |options| options.priority);
+        let policy = self.options.action_queue_policy;
+        if let Some(rejected) = workers.push(action, priority, policy) {
+            warn!(
+                "Action queue is full, rejecting {} ({})",
+                rejected.id, rejected.name
+            );
+            self.reject_action(&rejected.id, "action queue full");
+        }
+    }
+
+    /// Report queued action `id` as failed because of `error`, without running it
+    fn reject_action(&self, id: &str, error: &str) {
+        let Some(running) = self.running_actions.lock().unwrap().remove(id) else {
+            return;
+        };
+        if let Err(e) = self.finish_action(id, &running.status, ActionState::Failed, 0, &[error]) {
+            error!("Failed to publish status of action {id}: {e}");
+        }
+    }
+
+    fn cancel_action(&self, action: &Action) {
+        let Some(cancel) = action
+            .payload
+            .as_deref()
+            .and_then(|payload| serde_json::from_str::<CancelAction>(payload).ok())
+        else {
+            error!("Invalid payload for {CANCEL_ACTION}");
+            self.publish_action_status(&action.id, 0, "Failed", Some(&["invalid payload"]))
+                .ok();
+            return;
+        };
+
+        let token = self
+            .running_actions
+            .lock()
+            .unwrap()
+            .get(&cancel.action_id)
+            .map(|running| running.cancellation.clone());
+        match token {
+            Some(token) => {
+                info!("Cancelling action {} ({})", cancel.action_id, cancel.name);
+                token.cancel();
+                self.publish_action_status(&action.id, 100, "Completed", None)
+                    .ok();
+            }
+            None => {
+                warn!("Action {} isn't running, can't cancel it", cancel.action_id);
+                self.publish_action_status(&action.id, 0, "Failed", Some(&["action not running"]))
+                    .ok();
+            }
+        }
+    }
+
+    /// Run actions from `actions` till it is closed, meant to be run by each worker
+    pub(crate) fn run_actions(self: &Arc<Self>, actions: &ActionQueue) {
+        while let Some(action) = actions.pop() {
+            self.run_action(action);
+        }
+    }
+
+    fn run_action(self: &Arc<Self>, action: Action) {
+        let options = self
+            .action_options
+            .lock()
+            .unwrap()
+            .get(&action.name)
+            .copied()
+            .unwrap_or_default();
+        let status = match self.running_actions.lock().unwrap().get_mut(&action.id) {
+            Some(running) => {
+                running.options = options;
+                running.started_at = Some(self.clock.now());
+                running.worker = Some(Worker::current());
+                if options.timeout.is_some() {
+                    self.deadline_alarm.ring();
+                }
+                running.status.clone()
+            }
+            None => Arc::default(),
+        };
+        let context = ActionContext {
+            client: self.clone(),
+            id: action.id.clone(),
+            cancellation: action.cancellation.clone(),
+            status: status.clone(),
+        };
+        let id = action.id.clone();
+
+        // not holding on to map, so that handlers can (un)register handlers
+        let action_fn = self
+            .action_handles
+            .lock()
+            .unwrap()
+            .get(&action.name)
+            .cloned()
+            .or_else(|| self.fallback_action_handle.lock().unwrap().clone());
+
+        match action_fn {
+            // context is dropped right away, reporting action as cancelled
+            _ if context.is_cancelled() => {}
+            Some(action_fn) => {
+                // only where panics unwind, with `panic_abort` ( as on ESP-IDF ) device restarts
+                let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                    // handler which panicked earlier is still usable
+                    let mut action_fn = action_fn.lock().unwrap_or_else(PoisonError::into_inner);
+                    action_fn(action, context)
+                }));
+                if let Err(panic) = result {
+                    let message = format!("handler panicked: {}", panic_message(&panic));
+                    error!("Action {id} failed, {message}");
+                    self.finish_action(&id, &status, ActionState::Failed, 0, &[&message])
+                        .ok();
+                }
+            }
+            None => {
+                error!("Action handle does not exists for {}", action.name);
+                let error = format!("unsupported action: {}", action.name);
+                if let Err(e) = context.fail(&[&error]) {
+                    error!("Failed to publish status of action {id}: {e}");
+                }
+            }
+        }
+
+        // context might still be alive on another thread, but handler isn't hung
+        if let Some(running) = self.running_actions.lock().unwrap().get_mut(&id) {
+            running.handler_returned = true;
+        }
+    }
+
+    /// Fail actions which ran past their timeout, resetting device if a handler is hung
+    ///
+    /// Returns whether any action still has a deadline to check.
+    pub(crate) fn check_timeouts(&self) -> bool {
+        let now = self.clock.now();
+        let mut timed_out = Vec::new();
+        let mut hung = None;
+
+        let mut running_actions = self.running_actions.lock().unwrap();
+        for (id, running) in running_actions.iter_mut() {
+            let (Some(started_at), Some(timeout)) = (running.started_at, running.options.timeout)
+            else {
+                continue;
+            };
+            let elapsed = now.saturating_duration_since(started_at);
+            if elapsed < timeout {
+                continue;
+            }
+
+            if !running.timed_out {
+                running.timed_out = true;
+                running.cancellation.cancel();
+                timed_out.push((id.clone(), running.status.clone(), timeout));
+            }
+            if let (Some(reset_after), Some(worker)) = (running.options.reset_after, running.worker)
+            {
+                if !running.handler_returned && !running.reset && elapsed >= timeout + reset_after {
+                    running.reset = true;
+                    hung = Some((id.clone(), worker));
+                }
+            }
+        }
+        let has_deadlines = running_actions.values().any(RunningAction::has_deadline);
+        drop(running_actions);
+
+        for (id, status, timeout) in timed_out {
+            error!("Action {id} timed out after {timeout:?}");
+            let error = format!("timed out after {timeout:?}");
+            if let Err(e) = self.finish_action(&id, &status, ActionState::Failed, 0, &[&error]) {
+                error!("Failed to publish status of action {id}: {e}");
+            }
+        }
+
+        if let Some((id, worker)) = hung {
+            error!("Handler of action {id} is hung, resetting device");
+            match &self.reset_hook {
+                Some(reset_hook) => reset_hook(&ResetReason::HungHandler(id)),
+                None => worker.reset_hung(),
+            }
+        }
+
+        has_deadlines
+    }
+
+    /// Send terminal `state` of action `id`, unless one was already sent
+    fn finish_action(
+        &self,
+        id: &str,
+        status: &Mutex<StatusTracker>,
+        state: ActionState,
+        progress: u32,
+        errors: &[&str],
+    ) -> Result<()> {
+        let mut status = status.lock().unwrap();
+        if status.terminal {
+            warn!("Action {id} already finished, not sending {state:?}");
+            return Ok(());
+        }
+
+        self.publish_action_status(id, progress, state.as_str(), Some(errors))?;
+        status.terminal = true;
+        self.journal.lock().unwrap().finish(id);
+
+        Ok(())
+    }
+
+    /// Report final status of actions which were running when device reset
+    pub(crate) fn report_interrupted_actions(&self) {
+        let interrupted = self.journal.lock().unwrap().take_interrupted();
+        for entry in interrupted {
+            let (state, error) = entry.status_after_reset();
+            info!(
+                "Action {} ({}) was interrupted by reset, reporting {state:?}",
+                entry.id, entry.name
+            );
+            let result = match error {
+                None => self.publish_action_status(&entry.id, 100, state.as_str(), None),
+                Some(error) => {
+                    self.publish_action_status(&entry.id, 0, state.as_str(), Some(&[error]))
+                }
+            };
+            match result {
+                Ok(_) => self.journal.lock().unwrap().finish(&entry.id),
+                Err(e) => {
+                    error!("Failed to publish status of action {}: {e}", entry.id);
+                    // retried on next connect
+                    self.journal.lock().unwrap().requeue(entry);
+                }
+            }
+        }
+    }
+
+    /// Report actions completed by a reset which were held back till firmware was confirmed,
+    /// failing them if it was rolled back
+    #[cfg(feature = "esp-idf")]
+    pub(crate) fn release_reset_completions(&self, firmware_valid: bool) {
+        self.journal
+            .lock()
+            .unwrap()
+            .release_completions(firmware_valid);
+        // reported on connect otherwise, or on next boot after a roll back
+        if self.connection_state() == crate::ConnectionState::Connected {
+            self.report_interrupted_actions();
+        }
+    }
+
+    /// Publish names of actions device can handle to `supported_actions` stream
+    pub(crate) fn advertise_actions(&self) {
+        let mut actions: Vec<_> = self
+            .action_handles
+            .lock()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect();
+        actions.push(CANCEL_ACTION.into());
+        let fallback = self.fallback_action_handle.lock().unwrap().is_some();
+
+        let payload = SupportedActions { actions, fallback };
+        if let Err(e) = self.publish(SUPPORTED_ACTIONS, payload) {
+            error!("Failed to advertise actions: {e}");
+        }
+    }
+}
+
+/// Actions waiting for a worker, picked up by priority and in order of arrival among equals
+pub(crate) struct ActionQueue {
+    capacity: usize,
+    state: Mutex<QueueState>,
+    available: Condvar,
+}
+
+#[derive(Default)]
+struct QueueState {
+    actions: VecDeque<(u8, Action)>,
+    closed: bool,
+}
+
+impl ActionQueue {
+    pub fn new(capacity: usize) -> Self {
+        ActionQueue {
+            capacity: capacity.max(1),
+            state: Mutex::default(),
+            available: Condvar::new(),
+        }
+    }
+
+    /// Queue `action`, returning the action which was rejected to make room, if any
+    ///
+    /// Rejected action is the one of lowest priority, with `policy` deciding between queued and
+    /// new action when their priorities are equal.
+    pub fn push(&self, action: Action, priority: u8, policy: DropPolicy) -> Option<Action> {
+        let mut state = self.state.lock().unwrap();
+        let mut rejected = None;
+
+        if state.actions.len() >= self.capacity {
+            // oldest of queued actions with the lowest priority
+            let (lowest, &(lowest_priority, _)) = state
+                .actions
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, (priority, _))| *priority)
+                .expect("queue is full");
+            if priority < lowest_priority
+                || (priority == lowest_priority && policy == DropPolicy::Newest)
+            {
+                return Some(action);
+            }
+            rejected = state.actions.remove(lowest).map(|(_, action)| action);
+        }
+
+        state.actions.push_back((priority, action));
+        self.available.notify_one();
+
+        rejected
+    }
+
+    /// Wait for next action to run, `None` once queue is closed and empty
+    pub fn pop(&self) -> Option<Action> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            let next = state
+                .actions
+                .iter()
+                .enumerate()
+                .max_by_key(|(index, (priority, _))| (*priority, Reverse(*index)))
+                .map(|(index, _)| index);
+            if let Some(next) = next {
+                return state.actions.remove(next).map(|(_, action)| action);
+            }
+            if state.closed {
+                return None;
+            }
+            state = self.available.wait(state).unwrap();
+        }
+    }
+
+    /// Let workers exit once queued actions are done
+    pub fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.available.notify_all();
+    }
+}
+
+/// Wakes thread checking deadlines of actions, which sleeps while no action has one
+#[derive(Default)]
+pub(crate) struct DeadlineAlarm {
+    state: Mutex<AlarmState>,
+    rung: Condvar,
+}
+
+#[derive(Default)]
+struct AlarmState {
+    rung: bool,
+    closed: bool,
+}
+
+impl DeadlineAlarm {
+    /// Wake checking thread, as an action with a deadline started
+    pub fn ring(&self) {
+        self.state.lock().unwrap().rung = true;
+        self.rung.notify_all();
+    }
+
+    /// Wait till alarm rings, `false` once it is closed
+    pub fn wait(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        while !state.rung && !state.closed {
+            state = self.rung.wait(state).unwrap();
+        }
+        state.rung = false;
+        !state.closed
+    }
+
+    /// Let checking thread exit
+    pub fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.rung.notify_all();
+    }
+}
+
+/// Why client resets device, handed to [`reset_hook`](crate::ByteBeamClientBuilder::reset_hook)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ResetReason {
+    /// Handler of action with this id didn't return within `reset_after` past its timeout
+    HungHandler(String),
+    /// Action with this id asked for a restart, e.g. [`REBOOT`](crate::management::REBOOT) or
+    /// a firmware update
+    Restart(String),
+}
+
+pub(crate) type ResetHook = Arc<dyn Fn(&ResetReason) + Send + Sync>;
+
+/// Task which runs a handler, so that it can be pointed out to task watchdog once it hangs
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Worker {
+    #[cfg(feature = "esp-idf")]
+    task: usize,
+}
+
+impl Worker {
+    fn current() -> Self {
+        Worker {
+            #[cfg(feature = "esp-idf")]
+            task: unsafe { esp_idf_sys::xTaskGetCurrentTaskHandle() } as usize,
+        }
+    }
+
+    /// Reset device because handler running on worker is hung
+    fn reset_hung(self) {
+        #[cfg(feature = "esp-idf")]
+        crate::esp::system::watchdog_reset(self.task);
+        #[cfg(not(feature = "esp-idf"))]
+        std::process::abort();
+    }
+}
+
+/// State of an action, as shown on Bytebeam cloud
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ActionState {
+    /// Action is running
+    Progress,
+    /// Action finished successfully
+    Completed,
+    /// Action couldn't be finished
+    Failed,
+    /// Action was stopped on request of cloud
+    Cancelled,
+}
+
+impl ActionState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ActionState::Progress => "Progress",
+            ActionState::Completed => "Completed",
+            ActionState::Failed => "Failed",
+            ActionState::Cancelled => "Cancelled",
+        }
+    }
+
+    /// Whether no more statuses can follow this one
+    pub fn is_terminal(&self) -> bool {
+        *self != ActionState::Progress
+    }
+}
+
+#[derive(Default)]
+struct StatusTracker {
+    terminal: bool,
+    progress_sent_at: Option<Instant>,
+}
+
+/// Handle given to action handlers for reporting status of action
+///
+/// Exactly one terminal status ( completed, failed or cancelled ) is sent for every action. Only
+/// the first call to [`complete`](Self::complete) or [`fail`](Self::fail) counts, and if context
+/// is dropped without either, action is reported as cancelled if cloud asked for it, or failed
+/// otherwise. Progress updates are rate limited, see
+/// [`ClientOptions::action_progress_interval`](crate::ClientOptions::action_progress_interval).
+///
+/// Context can be moved to another thread, so that action keeps running after handler returns.
+///
+/// # Example
+/// ```no_run
+/// use std::{thread, time::Duration};
+/// use bytebeam_esp_rs::{Action, ActionContext, ByteBeamClient};
+///
+/// fn calibrate(action: Action, ctx: ActionContext) {
+///     for step in 0..10 {
+///         if ctx.is_cancelled() {
+///             return;
+///         }
+///         ctx.log(format!("calibration step {step}")).ok();
+///         thread::sleep(Duration::from_secs(1));
+///         ctx.progress(step * 10).ok();
+///     }
+///     ctx.complete().ok();
+/// }
+///
+/// let bytebeam_client = ByteBeamClient::builder().connect()?;
+/// bytebeam_client.register_action_handle("calibrate".into(), calibrate);
+/// # anyhow::Ok(())
+/// ```
+pub struct ActionContext<T: Transport = DefaultTransport> {
+    client: Arc<ByteBeamClient<T>>,
+    id: String,
+    cancellation: CancellationToken,
+    status: Arc<Mutex<StatusTracker>>,
+}
+
+impl<T: Transport> ActionContext<T> {
+    /// Client which received the action
+    pub fn client(&self) -> &Arc<ByteBeamClient<T>> {
+        &self.client
+    }
+
+    /// Id of action
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Whether cloud asked to cancel action
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellation.is_cancelled()
+    }
+
+    /// Report that `percentage` of action is done, skipped if last update was sent too recently
+    pub fn progress(&self, percentage: u32) -> Result<()> {
+        let mut status = self.status.lock().unwrap();
+        if status.terminal {
+            warn!("Action {} already finished, not sending progress", self.id);
+            return Ok(());
+        }
+
+        let interval = self.client.options.action_progress_interval;
+        let now = self.client.clock.now();
+        if status
+            .progress_sent_at
+            .is_some_and(|sent_at| now.duration_since(sent_at) < interval)
+        {
+            return Ok(());
+        }
+        status.progress_sent_at = Some(now);
+
+        self.client
+            .publish_action_status(
+                &self.id,
+                percentage.min(100),
+                ActionState::Progress.as_str(),
+                None,
+            )
+            .map(drop)
+    }
+
+    /// Report that action finished successfully
+    pub fn complete(&self) -> Result<()> {
+        self.finish(ActionState::Completed, 100, &[])
+    }
+
+    /// Report that action failed because of `errors`
+    pub fn fail(&self, errors: &[&str]) -> Result<()> {
+        self.finish(ActionState::Failed, 0, errors)
+    }
+
+    /// Report action as completed if device resets before it sends a final status
+    ///
+    /// Meant for actions which end with a reset, e.g. firmware updates, so that they are
+    /// completed once device boots up again. Without a
+    /// [`journal_store`](crate::ByteBeamClientBuilder::journal_store) nothing remembers action
+    /// across the reset, so it is reported as completed right away instead.
+    pub fn complete_after_reset(&self) {
+        let mut journal = self.client.journal.lock().unwrap();
+        if journal.is_persistent() {
+            journal.complete_after_reset(&self.id);
+            return;
+        }
+        drop(journal);
+
+        if let Err(e) = self.complete() {
+            error!("Failed to publish status of action {}: {e}", self.id);
+        }
+    }
+
+    /// Publish `message` to `action_logs` stream, tagged with id of action
+    pub fn log(&self, message: impl Into<String>) -> Result<u32> {
+        self.client.publish(
+            ACTION_LOGS,
+            ActionLog {
+                action_id: &self.id,
+                message: message.into(),
+            },
+        )
+    }
+
+    /// Publish `response` to `action_responses` stream, for actions which return data to cloud
+    pub fn respond(&self, response: impl Serialize) -> Result<u32> {
+        self.client.publish(
+            ACTION_RESPONSES,
+            ActionResponse {
+                action_id: &self.id,
+                response,
+            },
+        )
+    }
+
+    fn finish(&self, state: ActionState, progress: u32, errors: &[&str]) -> Result<()> {
+        self.client
+            .finish_action(&self.id, &self.status, state, progress, errors)
+    }
+}
+
+impl<T: Transport> Drop for ActionContext<T> {
+    fn drop(&mut self) {
+        self.client.running_actions.lock().unwrap().remove(&self.id);
+
+        // worker reports panic itself, with a better message
+        if thread::panicking() || self.status.lock().unwrap().terminal {
+            return;
+        }
+
+        let result = if self.is_cancelled() {
+            self.finish(ActionState::Cancelled, 0, &[])
+        } else {
+            error!("Action {} finished without reporting status", self.id);
+            self.fail(&["action finished without reporting status"])
+        };
+        if let Err(e) = result {
+            error!("Failed to publish status of action {}: {e}", self.id);
+        }
+    }
+}
+
+/// Name of stream [`ActionContext::log`] publishes to
+const ACTION_LOGS: &str = "action_logs";
+
+/// Name of stream [`ActionContext::respond`] publishes to
+const ACTION_RESPONSES: &str = "action_responses";
+
+#[derive(Serialize)]
+struct SupportedActions {
+    actions: Vec<String>,
+    /// Whether actions not in `actions` are handled as well
+    fallback: bool,
+}
+
+#[derive(Serialize)]
+struct ActionResponse<'a, R> {
+    action_id: &'a str,
+    response: R,
+}
+
+#[derive(Serialize)]
+struct ActionLog<'a> {
+    action_id: &'a str,
+    message: String,
+}
+
+impl<T: Transport> ByteBeamClient<T> {
+    /// Restart device for action `id`, or call reset hook if one is set
+    ///
+    /// There is nothing to restart on host, so it is only logged there.
+    pub(crate) fn restart_device(&self, id: &str) {
+        if let Some(reset_hook) = &self.reset_hook {
+            reset_hook(&ResetReason::Restart(id.into()));
+            return;
+        }
+
+        #[cfg(feature = "esp-idf")]
+        unsafe {
+            esp_idf_sys::esp_restart()
+        };
+        #[cfg(not(feature = "esp-idf"))]
+        warn!("Action {id} asked for a restart, which is skipped on host");
+    }
+}
+
+fn panic_message(panic: &Box<dyn Any + Send>) -> &str {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown reason"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::{self, Receiver, Sender};
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        clock::MockClock,
+        transport::{
+            testing::{action_states, device_config, send_action, stream_messages, wait_for},
+            MockBroker, MockTransport,
+        },
+        ConnectionState,
+    };
+
+    fn connect(clock: &MockClock) -> (Arc<ByteBeamClient<MockTransport>>, MockBroker) {
+        connect_recording_resets(clock, Arc::default())
+    }
+
+    /// Client which records resets in `resets` instead of resetting
+    fn connect_recording_resets(
+        clock: &MockClock,
+        resets: Arc<Mutex<Vec<ResetReason>>>,
+    ) -> (Arc<ByteBeamClient<MockTransport>>, MockBroker) {
+        let (transport, events, broker) = MockTransport::new();
+        let client = ByteBeamClient::builder()
+            .device_config(device_config())
+            .clock(clock.clone())
+            .action_workers(2)
+            .reset_hook(move |reason| resets.lock().unwrap().push(reason.clone()))
+            .connect_with(transport, events)
+            .unwrap();
+        broker.connect();
+        wait_for(|| client.connection_state() == ConnectionState::Connected);
+
+        (client, broker)
+    }
+
+    /// Handler of `name` which runs till it is released, sending `false` once it starts and
+    /// whether it was cancelled once it returns
+    fn register_blocking(
+        client: &ByteBeamClient<MockTransport>,
+        name: &str,
+        options: ActionOptions,
+    ) -> (Receiver<bool>, Sender<()>) {
+        let (started_tx, started) = mpsc::channel();
+        let (release, released) = mpsc::channel();
+        client.register_action_handle(name.into(), move |_, ctx| {
+            started_tx.send(false).unwrap();
+            released.recv().unwrap();
+            ctx.complete().ok();
+            started_tx.send(ctx.is_cancelled()).unwrap();
+        });
+        client.set_action_options(name, options);
+
+        (started, release)
+    }
+
+    fn wait_checks() {
+        thread::sleep(3 * TIMEOUT_CHECK_INTERVAL);
+    }
+
+    #[test]
+    fn action_past_deadline_fails_once() {
+        let clock = MockClock::new();
+        let (client, broker) = connect(&clock);
+        let options = ActionOptions {
+            timeout: Some(Duration::from_secs(30)),
+            ..Default::default()
+        };
+        let (handler, release) = register_blocking(&client, "calibrate", options);
+        assert!(!client.check_timeouts());
+
+        send_action(&broker, "1", "calibrate", "");
+        handler.recv().unwrap();
+        assert!(client.check_timeouts());
+        clock.advance(Duration::from_secs(29));
+        wait_checks();
+        assert!(action_states(&broker, "1").is_empty());
+
+        clock.advance(Duration::from_secs(1));
+        wait_for(|| action_states(&broker, "1") == ["Failed"]);
+        // nothing left to check, handler has no `reset_after`
+        assert!(!client.check_timeouts());
+
+        clock.advance(Duration::from_secs(60));
+        wait_checks();
+        release.send(()).unwrap();
+        assert!(handler.recv().unwrap(), "handler wasn't cancelled");
+        wait_checks();
+        assert_eq!(action_states(&broker, "1"), ["Failed"]);
+    }
+
+    #[test]
+    fn resets_only_while_handler_hasnt_returned() {
+        let clock = MockClock::new();
+        let resets = Arc::default();
+        let (client, broker) = connect_recording_resets(&clock, Arc::clone(&resets));
+        let options = ActionOptions {
+            timeout: Some(Duration::from_secs(10)),
+            reset_after: Some(Duration::from_secs(5)),
+            ..Default::default()
+        };
+        let (hung, release_hung) = register_blocking(&client, "hang", options);
+        // handler returns right away, while action keeps running on context it hands out
+        let (contexts, detached) = mpsc::channel();
+        client.register_action_handle("detach".into(), move |_, ctx| {
+            contexts.send(ctx).unwrap();
+        });
+        client.set_action_options("detach", options);
+
+        send_action(&broker, "hung", "hang", "");
+        send_action(&broker, "returned", "detach", "");
+        hung.recv().unwrap();
+        let detached = detached.recv().unwrap();
+        wait_for(|| client.running_actions.lock().unwrap()["returned"].handler_returned);
+
+        clock.advance(Duration::from_secs(10));
+        wait_for(|| action_states(&broker, "hung") == ["Failed"]);
+        wait_for(|| action_states(&broker, "returned") == ["Failed"]);
+        assert!(detached.is_cancelled());
+
+        clock.advance(Duration::from_secs(4));
+        wait_checks();
+        assert!(resets.lock().unwrap().is_empty());
+
+        clock.advance(Duration::from_secs(1));
+        wait_for(|| !resets.lock().unwrap().is_empty());
+        clock.advance(Duration::from_secs(60));
+        wait_checks();
+        assert_eq!(
+            *resets.lock().unwrap(),
+            [ResetReason::HungHandler("hung".into())]
+        );
+
+        release_hung.send(()).unwrap();
+        hung.recv().unwrap();
+        drop(detached);
+    }
+
+    #[test]
+    fn advertises_actions_only_when_enabled() {
+        for advertise in [false, true] {
+            let (transport, events, broker) = MockTransport::new();
+            let client = ByteBeamClient::builder()
+                .device_config(device_config())
+                .advertise_actions(advertise)
+                .connect_with(transport, events)
+                .unwrap();
+            client.register_action_handle("toggle".into(), |_, _| {});
+            broker.connect();
+            wait_for(|| client.connection_state() == ConnectionState::Connected);
+
+            if advertise {
+                wait_for(|| !stream_messages(&broker, SUPPORTED_ACTIONS).is_empty());
+                let advertised = stream_messages(&broker, SUPPORTED_ACTIONS);
+                assert_eq!(advertised.len(), 1);
+                assert_eq!(
+                    advertised[0][0]["actions"],
+                    json!(["toggle", CANCEL_ACTION])
+                );
+            } else {
+                wait_checks();
+                assert!(stream_messages(&broker, SUPPORTED_ACTIONS).is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn reports_interrupted_action_again_if_publish_fails() {
+        let path = std::env::temp_dir().join(format!("journal_{}", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"[{"id":"1","name":"calibrate","state":"running"}]"#,
+        )
+        .unwrap();
+        let (transport, events, broker) = MockTransport::new();
+        let client = ByteBeamClient::builder()
+            .device_config(device_config())
+            .journal_store(crate::store::File::new(&path))
+            .connect_with(transport, events)
+            .unwrap();
+
+        broker.fail_publishes(1);
+        broker.connect();
+        wait_for(|| client.connection_state() == ConnectionState::Connected);
+        wait_checks();
+        assert!(action_states(&broker, "1").is_empty());
+
+        broker.disconnect();
+        broker.connect();
+        wait_for(|| action_states(&broker, "1") == ["Failed"]);
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn throttles_progress_by_client_clock() {
+        let clock = MockClock::new();
+        let (client, broker) = connect(&clock);
+        let (contexts, context) = mpsc::channel();
+        client.register_action_handle("calibrate".into(), move |_, ctx| {
+            contexts.send(ctx).unwrap();
+        });
+        send_action(&broker, "1", "calibrate", "");
+        let ctx = context.recv().unwrap();
+
+        ctx.progress(10).unwrap();
+        ctx.progress(20).unwrap();
+        clock.advance(Duration::from_secs(1));
+        ctx.progress(30).unwrap();
+        assert_eq!(action_states(&broker, "1"), ["Progress", "Progress"]);
+        ctx.complete().unwrap();
+    }
+}
@@ -0,0 +1,322 @@
+//! Batching of stream records into fewer, larger messages
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use log::error;
+use serde::Serialize;
+
+use crate::{
+    error::Result,
+    protocol::{self, StreamPayload},
+    transport::{DefaultTransport, Transport},
+    ByteBeamClient, ByteBeamError,
+};
+
+/// Thresholds at which a [`StreamBatcher`] publishes collected records
+///
+/// Whichever threshold is hit first triggers the flush.
+#[derive(Clone, Copy, Debug)]
+pub struct BatchOptions {
+    /// Number of records in a batch
+    pub max_records: usize,
+    /// Size of batch in bytes, should stay below buffer size of MQTT client
+    pub max_bytes: usize,
+    /// Time since first record of batch was collected, only checked on push and
+    /// [`StreamBatcher::flush_if_due`]
+    pub max_delay: Duration,
+}
+
+impl Default for BatchOptions {
+    fn default() -> Self {
+        BatchOptions {
+            max_records: 100,
+            max_bytes: 4 * 1024,
+            max_delay: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Collects records of a stream and publishes them as a single JSON array
+///
+/// Each record gets the next sequence number of stream managed by client, and the timestamp at
+/// which it was pushed. Remaining records are flushed on drop.
+///
+/// Nothing runs in the background: `max_delay` is only checked when a record is pushed, or when
+/// [`flush_if_due`](Self::flush_if_due) is called. Records of a stream which goes quiet wait
+/// till then, so call `flush_if_due` periodically if that can happen.
+///
+/// # Example
+/// ```no_run
+/// use std::time::Duration;
+/// use bytebeam_esp_rs::{BatchOptions, ByteBeamClient, StreamBatcher};
+/// use serde::Serialize;
+///
+/// #[derive(Serialize)]
+/// struct Imu {
+///     ax: f32,
+///     ay: f32,
+///     az: f32,
+/// }
+///
+/// let bytebeam_client = ByteBeamClient::builder().connect()?;
+/// let mut imu = StreamBatcher::new(
+///     bytebeam_client,
+///     "imu",
+///     BatchOptions {
+///         max_records: 50,
+///         max_delay: Duration::from_secs(5),
+///         ..Default::default()
+///     },
+/// );
+///
+/// loop {
+///     imu.push(Imu { ax: 0.0, ay: 0.0, az: 9.8 })?;
+///     std::thread::sleep(Duration::from_millis(100));
+/// }
+/// # anyhow::Ok(())
+/// ```
+pub struct StreamBatcher<T: Transport = DefaultTransport> {
+    client: Arc<ByteBeamClient<T>>,
+    topic: String,
+    options: BatchOptions,
+    /// Records collected so far, as an unterminated JSON array
+    batch: Vec<u8>,
+    count: usize,
+    first_pushed_at: Option<Instant>,
+    stream_name: String,
+}
+
+impl<T: Transport> StreamBatcher<T> {
+    /// Batch records of `stream_name`, publishing them through `client`
+    pub fn new(client: Arc<ByteBeamClient<T>>, stream_name: &str, options: BatchOptions) -> Self {
+        let topic = protocol::stream_topic(&client.project_id, &client.device_id, stream_name);
+
+        StreamBatcher {
+            client,
+            topic,
+            options,
+            batch: Vec::new(),
+            count: 0,
+            first_pushed_at: None,
+            stream_name: stream_name.into(),
+        }
+    }
+
+    /// Add a record to batch, publishing batch if a threshold is hit
+    ///
+    /// Fails if batch couldn't be published, which drops the record only if there was no room
+    /// left for it in batch.
+    pub fn push(&mut self, payload: impl Serialize) -> Result<()> {
+        let record = serde_json::to_vec(&StreamPayload {
+            id: &self.client.device_id,
+            sequence: self.client.next_sequence(&self.stream_name, 1),
+            timestamp: protocol::timestamp(),
+            payload,
+        })
+        .map_err(ByteBeamError::Serialization)?;
+
+        // `[` or `,` before record, `]` after it
+        if self.count > 0 && self.batch.len() + record.len() + 2 > self.options.max_bytes {
+            self.flush()?;
+        }
+
+        self.batch.push(if self.count == 0 { b'[' } else { b',' });
+        self.batch.extend_from_slice(&record);
+        self.count += 1;
+        let now = self.client.clock.now();
+        self.first_pushed_at.get_or_insert(now);
+
+        if self.count >= self.options.max_records || self.batch.len() + 1 >= self.options.max_bytes
+        {
+            return self.flush();
+        }
+        self.flush_if_due()
+    }
+
+    /// Publish batch if its oldest record has waited for longer than `max_delay`
+    pub fn flush_if_due(&mut self) -> Result<()> {
+        match self.first_pushed_at {
+            Some(first_pushed_at)
+                if self.client.clock.now() - first_pushed_at >= self.options.max_delay =>
+            {
+                self.flush()
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Publish all collected records right away
+    ///
+    /// Records are kept for the next flush if publishing fails, unless they were handed to the
+    /// offline queue, which reports records it drops with [`ByteBeamError::RecordsDropped`].
+    pub fn flush(&mut self) -> Result<()> {
+        if self.count == 0 {
+            return Ok(());
+        }
+
+        self.batch.push(b']');
+        let result = self.client.publish_stream_data(&self.topic, &self.batch);
+        if let Err(e) = &result {
+            if !matches!(e, ByteBeamError::RecordsDropped(_)) {
+                self.batch.pop();
+                return result.map(drop);
+            }
+        }
+
+        self.batch.clear();
+        self.count = 0;
+        self.first_pushed_at = None;
+        result.map(drop)
+    }
+
+    /// Number of records waiting to be published
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    /// Whether there are no records waiting to be published
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+}
+
+impl<T: Transport> Drop for StreamBatcher<T> {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            error!("Failed to flush batch: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::{
+        clock::MockClock,
+        transport::{
+            testing::{device_config, stream_messages},
+            MockBroker, MockTransport,
+        },
+    };
+
+    fn client(clock: &MockClock) -> (Arc<ByteBeamClient<MockTransport>>, MockBroker) {
+        let (transport, events, broker) = MockTransport::new();
+        let client = ByteBeamClient::builder()
+            .device_config(device_config())
+            .clock(clock.clone())
+            .connect_with(transport, events)
+            .unwrap();
+        (client, broker)
+    }
+
+    fn options(max_records: usize, max_bytes: usize) -> BatchOptions {
+        BatchOptions {
+            max_records,
+            max_bytes,
+            max_delay: Duration::from_secs(10),
+        }
+    }
+
+    /// Field `key` of records in each batch of `imu` stream
+    fn fields(broker: &MockBroker, key: &str) -> Vec<Vec<Value>> {
+        stream_messages(broker, "imu")
+            .into_iter()
+            .map(|records| records.iter().map(|record| record[key].clone()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn flushes_at_max_records() {
+        let (client, broker) = client(&MockClock::new());
+        let mut imu = StreamBatcher::new(client, "imu", options(3, 4096));
+        for n in 0..7 {
+            imu.push(json!({ "n": n })).unwrap();
+        }
+
+        assert_eq!(fields(&broker, "n"), [[0, 1, 2], [3, 4, 5]]);
+        assert_eq!(fields(&broker, "sequence"), [[1, 2, 3], [4, 5, 6]]);
+        assert_eq!(imu.len(), 1);
+    }
+
+    #[test]
+    fn flushes_before_exceeding_max_bytes() {
+        let (client, broker) = client(&MockClock::new());
+        let mut imu = StreamBatcher::new(client, "imu", options(100, 400));
+        for n in 0..5 {
+            imu.push(json!({ "n": n, "padding": "x".repeat(100) }))
+                .unwrap();
+        }
+
+        assert_eq!(fields(&broker, "n"), [[0, 1], [2, 3]]);
+        for message in broker.published() {
+            assert!(message.payload.len() <= 400);
+        }
+        assert_eq!(imu.len(), 1);
+    }
+
+    #[test]
+    fn flushes_after_max_delay() {
+        let clock = MockClock::new();
+        let (client, broker) = client(&clock);
+        let mut imu = StreamBatcher::new(client, "imu", options(100, 4096));
+        imu.push(json!({ "n": 0 })).unwrap();
+        clock.advance(Duration::from_secs(5));
+        imu.push(json!({ "n": 1 })).unwrap();
+        imu.flush_if_due().unwrap();
+        assert!(fields(&broker, "n").is_empty());
+
+        // delay counts from the first record of batch
+        clock.advance(Duration::from_secs(5));
+        imu.flush_if_due().unwrap();
+        assert_eq!(fields(&broker, "n"), [[0, 1]]);
+        assert!(imu.is_empty());
+
+        imu.push(json!({ "n": 2 })).unwrap();
+        clock.advance(Duration::from_secs(10));
+        imu.push(json!({ "n": 3 })).unwrap();
+        assert_eq!(fields(&broker, "n"), [vec![0, 1], vec![2, 3]]);
+    }
+
+    #[test]
+    fn flushes_on_drop() {
+        let (client, broker) = client(&MockClock::new());
+        let mut imu = StreamBatcher::new(client, "imu", options(100, 4096));
+        imu.push(json!({ "n": 0 })).unwrap();
+        imu.push(json!({ "n": 1 })).unwrap();
+        assert!(fields(&broker, "n").is_empty());
+
+        drop(imu);
+        assert_eq!(fields(&broker, "n"), [[0, 1]]);
+    }
+
+    #[test]
+    fn keeps_records_when_publish_fails() {
+        let (client, broker) = client(&MockClock::new());
+        let mut imu = StreamBatcher::new(client, "imu", options(2, 4096));
+        imu.push(json!({ "n": 0 })).unwrap();
+        broker.fail_publishes(1);
+        assert!(imu.push(json!({ "n": 1 })).is_err());
+        assert_eq!(imu.len(), 2);
+
+        imu.flush().unwrap();
+        assert_eq!(fields(&broker, "n"), [[0, 1]]);
+    }
+
+    #[test]
+    fn publish_batch_sends_one_message() {
+        let (client, broker) = client(&MockClock::new());
+        client
+            .publish_batch("imu", 7, &[json!({ "n": 0 }), json!({ "n": 1 })])
+            .unwrap();
+
+        assert_eq!(fields(&broker, "n"), [[0, 1]]);
+        assert_eq!(fields(&broker, "sequence"), [[7, 8]]);
+        assert_eq!(fields(&broker, "id"), [["d", "d"]]);
+        let timestamps = &fields(&broker, "timestamp")[0];
+        assert_eq!(timestamps[0], timestamps[1]);
+    }
+}
@@ -0,0 +1,398 @@
+//! Builder for configuring [`ByteBeamClient`] before connecting
+use std::{
+    io,
+    sync::Arc,
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::{
+    action::{ResetHook, ResetReason},
+    clock::Clock,
+    config::ConfigSource,
+    connection::Backoff,
+    error::Result,
+    queue::{DropPolicy, OfflineQueueOptions},
+    store::BlobStore,
+    transport::{EventStream, QoS, Transport},
+    ByteBeamClient, ByteBeamError, DeviceConfig,
+};
+
+/// Stack size and priority of a thread spawned by the SDK
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ThreadOptions {
+    /// Stack size in bytes, platform default if `None`
+    pub stack_size: Option<usize>,
+    /// Priority of thread, platform default if `None`
+    ///
+    /// Only has an effect on ESP-IDF, where it is the FreeRTOS task priority
+    pub priority: Option<u8>,
+}
+
+impl ThreadOptions {
+    pub(crate) fn spawn<F>(&self, name: &str, f: F) -> io::Result<JoinHandle<()>>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let mut builder = thread::Builder::new().name(name.into());
+        if let Some(stack_size) = self.stack_size {
+            builder = builder.stack_size(stack_size);
+        }
+
+        #[cfg(feature = "esp-idf")]
+        return crate::esp::with_thread_priority(self.priority, || builder.spawn(f));
+        #[cfg(not(feature = "esp-idf"))]
+        builder.spawn(f)
+    }
+}
+
+/// Tunables of [`ByteBeamClient`]
+///
+/// Everything left as `None` falls back to the default of underlying MQTT client
+#[derive(Clone, Debug)]
+pub struct ClientOptions {
+    /// MQTT client id, device id is used if `None`
+    pub client_id: Option<String>,
+    /// Interval after which broker is pinged if nothing else was sent
+    pub keep_alive: Option<Duration>,
+    /// Size of buffer for incoming messages in bytes
+    pub buffer_size: Option<usize>,
+    /// Size of buffer for outgoing messages in bytes
+    pub out_buffer_size: Option<usize>,
+    /// Task running the MQTT client, only used by ESP-IDF client
+    pub mqtt_task: ThreadOptions,
+    /// Thread listening for messages from broker
+    pub event_thread: ThreadOptions,
+    /// Threads executing action handlers
+    pub action_thread: ThreadOptions,
+    /// Number of actions which can run at the same time
+    pub action_workers: usize,
+    /// Number of actions which can wait for a worker
+    pub action_queue_capacity: usize,
+    /// Which action to reject when action queue is full, among actions of the lowest priority
+    pub action_queue_policy: DropPolicy,
+    /// Minimum time between progress updates of an action
+    pub action_progress_interval: Duration,
+    /// Publish names of registered actions every time client connects
+    pub advertise_actions: bool,
+    /// Version of running firmware, which OTA updates are compared with and which is reported
+    /// on connect. Read from app descriptor on ESP-IDF if `None`
+    pub firmware_version: Option<String>,
+    /// Time firmware booted after an OTA update gets to connect with broker and pass its health
+    /// check, before it is rolled back. Only used by ESP-IDF client
+    pub firmware_check_timeout: Duration,
+    /// QoS for publishing stream data
+    pub stream_qos: QoS,
+    /// QoS for publishing action status
+    pub action_status_qos: QoS,
+    /// QoS for subscribing to actions
+    pub actions_qos: QoS,
+    /// Delay between attempts to reconnect with broker
+    pub reconnect_backoff: Backoff,
+    /// Queue holding stream data while offline, `None` by default so that data is published
+    /// straight away even while offline
+    pub offline_queue: Option<OfflineQueueOptions>,
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        Self {
+            client_id: None,
+            keep_alive: None,
+            buffer_size: None,
+            out_buffer_size: None,
+            mqtt_task: ThreadOptions::default(),
+            event_thread: ThreadOptions::default(),
+            action_thread: ThreadOptions::default(),
+            action_workers: 2,
+            action_queue_capacity: 16,
+            action_queue_policy: DropPolicy::Newest,
+            action_progress_interval: Duration::from_secs(1),
+            advertise_actions: false,
+            firmware_version: None,
+            firmware_check_timeout: Duration::from_secs(120),
+            stream_qos: QoS::AtLeastOnce,
+            action_status_qos: QoS::AtLeastOnce,
+            actions_qos: QoS::AtLeastOnce,
+            reconnect_backoff: Backoff::default(),
+            offline_queue: None,
+        }
+    }
+}
+
+/// Builder for [`ByteBeamClient`], created with [`ByteBeamClient::builder`]
+///
+/// # Example
+/// ```no_run
+/// use std::time::Duration;
+/// use bytebeam_esp_rs::ByteBeamClient;
+///
+/// let bytebeam_client = ByteBeamClient::builder()
+///     .keep_alive(Duration::from_secs(60))
+///     .buffer_size(2048)
+///     .action_thread_stack_size(8 * 1024)
+///     .connect()?;
+/// # anyhow::Ok(())
+/// ```
+#[derive(Default)]
+pub struct ByteBeamClientBuilder {
+    device_config: Option<DeviceConfig>,
+    config_source: Option<Box<dyn ConfigSource>>,
+    pub(crate) extensions: Extensions,
+    pub(crate) options: ClientOptions,
+    #[cfg(feature = "esp-idf")]
+    pub(crate) firmware_health_check: Option<crate::esp::rollback::HealthCheck>,
+}
+
+/// Implementations of extension points of client, platform default is used for ones not set
+#[derive(Default)]
+pub(crate) struct Extensions {
+    pub sequence_store: Option<Box<dyn BlobStore>>,
+    pub journal_store: Option<Box<dyn BlobStore>>,
+    pub clock: Option<Arc<dyn Clock>>,
+    pub reset_hook: Option<ResetHook>,
+    pub settings_store: Option<Box<dyn BlobStore>>,
+    pub download_store: Option<Box<dyn BlobStore>>,
+    /// Running firmware waits for confirmation after an update
+    pub firmware_pending_verify: bool,
+}
+
+impl ByteBeamClientBuilder {
+    /// Use `device_config` instead of reading it from a [`ConfigSource`]
+    pub fn device_config(mut self, device_config: DeviceConfig) -> Self {
+        self.device_config = Some(device_config);
+        self
+    }
+
+    /// Read device config from `config_source` instead of the platform default
+    ///
+    /// Default is [`Spiffs`](crate::config::Spiffs) on ESP-IDF, and `device_config.json` in
+    /// current directory on host
+    pub fn config_source(mut self, config_source: impl ConfigSource + 'static) -> Self {
+        self.config_source = Some(Box::new(config_source));
+        self
+    }
+
+    /// Persist sequence numbers of streams in `sequence_store`, so that they keep increasing
+    /// across reboots
+    ///
+    /// Without it, sequences start from `1` on every boot.
+    pub fn sequence_store(mut self, sequence_store: impl BlobStore + 'static) -> Self {
+        self.extensions.sequence_store = Some(Box::new(sequence_store));
+        self
+    }
+
+    /// Persist journal of recent actions in `journal_store`, so that redelivered actions are
+    /// skipped and actions interrupted by a reset get reported across reboots
+    ///
+    /// Without it, journal is kept only in RAM.
+    pub fn journal_store(mut self, journal_store: impl BlobStore + 'static) -> Self {
+        self.extensions.journal_store = Some(Box::new(journal_store));
+        self
+    }
+
+    /// Keep settings of device in `settings_store`, see [`settings`](crate::settings)
+    pub fn settings_store(mut self, settings_store: impl BlobStore + 'static) -> Self {
+        self.extensions.settings_store = Some(Box::new(settings_store));
+        self
+    }
+
+    /// Persist progress of firmware downloads in `download_store`, so that an update
+    /// interrupted by a reset resumes instead of downloading image again
+    ///
+    /// Without it, downloads only resume till device resets, see [`firmware`](crate::firmware).
+    pub fn download_store(mut self, download_store: impl BlobStore + 'static) -> Self {
+        self.extensions.download_store = Some(Box::new(download_store));
+        self
+    }
+
+    /// Read time for deadlines of actions from `clock`, mostly useful for tests
+    pub fn clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.extensions.clock = Some(Arc::new(clock));
+        self
+    }
+
+    /// Call `reset_hook` instead of resetting device, mostly useful for tests
+    pub fn reset_hook(mut self, reset_hook: impl Fn(&ResetReason) + Send + Sync + 'static) -> Self {
+        self.extensions.reset_hook = Some(Arc::new(reset_hook));
+        self
+    }
+
+    /// Replace all tunables at once
+    pub fn options(mut self, options: ClientOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// MQTT client id, defaults to device id
+    pub fn client_id(mut self, client_id: impl Into<String>) -> Self {
+        self.options.client_id = Some(client_id.into());
+        self
+    }
+
+    /// Interval after which broker is pinged if nothing else was sent
+    pub fn keep_alive(mut self, keep_alive: Duration) -> Self {
+        self.options.keep_alive = Some(keep_alive);
+        self
+    }
+
+    /// Size of buffer for incoming messages in bytes
+    pub fn buffer_size(mut self, buffer_size: usize) -> Self {
+        self.options.buffer_size = Some(buffer_size);
+        self
+    }
+
+    /// Size of buffer for outgoing messages in bytes
+    pub fn out_buffer_size(mut self, out_buffer_size: usize) -> Self {
+        self.options.out_buffer_size = Some(out_buffer_size);
+        self
+    }
+
+    /// Stack size and priority of ESP-IDF's MQTT task
+    pub fn mqtt_task(mut self, mqtt_task: ThreadOptions) -> Self {
+        self.options.mqtt_task = mqtt_task;
+        self
+    }
+
+    /// Stack size and priority of thread listening for messages from broker
+    pub fn event_thread(mut self, event_thread: ThreadOptions) -> Self {
+        self.options.event_thread = event_thread;
+        self
+    }
+
+    /// Stack size of thread listening for messages from broker
+    pub fn event_thread_stack_size(mut self, stack_size: usize) -> Self {
+        self.options.event_thread.stack_size = Some(stack_size);
+        self
+    }
+
+    /// Number of actions which can run at the same time, defaults to `2`
+    ///
+    /// Every worker is a thread with [`action_thread`](Self::action_thread) options.
+    pub fn action_workers(mut self, workers: usize) -> Self {
+        self.options.action_workers = workers;
+        self
+    }
+
+    /// Number of actions which can wait for a worker and which of them to reject once there are
+    /// more, defaults to 16 and rejecting new actions
+    ///
+    /// Rejected actions are reported as failed. Actions of higher
+    /// [`priority`](crate::ActionOptions::priority) push out ones of lower priority.
+    pub fn action_queue(mut self, capacity: usize, policy: DropPolicy) -> Self {
+        self.options.action_queue_capacity = capacity;
+        self.options.action_queue_policy = policy;
+        self
+    }
+
+    /// Minimum time between progress updates of an action, defaults to 1 second
+    pub fn action_progress_interval(mut self, interval: Duration) -> Self {
+        self.options.action_progress_interval = interval;
+        self
+    }
+
+    /// Whether to publish names of registered actions on connect, disabled by default
+    pub fn advertise_actions(mut self, advertise: bool) -> Self {
+        self.options.advertise_actions = advertise;
+        self
+    }
+
+    /// Version of running firmware, instead of the one in app descriptor
+    ///
+    /// Useful on host, or when app descriptor isn't set up with the version of firmware.
+    pub fn firmware_version(mut self, version: impl Into<String>) -> Self {
+        self.options.firmware_version = Some(version.into());
+        self
+    }
+
+    /// Stack size and priority of threads executing action handlers
+    pub fn action_thread(mut self, action_thread: ThreadOptions) -> Self {
+        self.options.action_thread = action_thread;
+        self
+    }
+
+    /// Stack size of threads executing action handlers
+    pub fn action_thread_stack_size(mut self, stack_size: usize) -> Self {
+        self.options.action_thread.stack_size = Some(stack_size);
+        self
+    }
+
+    /// QoS for publishing stream data, defaults to `AtLeastOnce`
+    pub fn stream_qos(mut self, qos: QoS) -> Self {
+        self.options.stream_qos = qos;
+        self
+    }
+
+    /// QoS for publishing action status, defaults to `AtLeastOnce`
+    pub fn action_status_qos(mut self, qos: QoS) -> Self {
+        self.options.action_status_qos = qos;
+        self
+    }
+
+    /// QoS for subscribing to actions, defaults to `AtLeastOnce`
+    pub fn actions_qos(mut self, qos: QoS) -> Self {
+        self.options.actions_qos = qos;
+        self
+    }
+
+    /// Delay between attempts to reconnect with broker, see [`Backoff`] for defaults
+    pub fn reconnect_backoff(mut self, backoff: Backoff) -> Self {
+        self.options.reconnect_backoff = backoff;
+        self
+    }
+
+    /// Hold stream data in a queue while offline, with given size limits and drop policy
+    ///
+    /// Without it, stream data is handed to transport straight away even while offline.
+    pub fn offline_queue(mut self, offline_queue: OfflineQueueOptions) -> Self {
+        self.options.offline_queue = Some(offline_queue);
+        self
+    }
+
+    /// Device config set on builder, or loaded from config source
+    pub(crate) fn load_device_config(
+        &mut self,
+        mut default_source: impl ConfigSource,
+    ) -> Result<DeviceConfig> {
+        if let Some(device_config) = self.device_config.take() {
+            return Ok(device_config);
+        }
+
+        match &mut self.config_source {
+            Some(config_source) => config_source.load(),
+            None => default_source.load(),
+        }
+    }
+
+    /// Create client on top of an already connected `transport`
+    ///
+    /// Device config must be set with either [`device_config`](Self::device_config) or
+    /// [`config_source`](Self::config_source)
+    pub fn connect_with<T: Transport>(
+        mut self,
+        transport: T,
+        events: impl EventStream,
+    ) -> Result<Arc<ByteBeamClient<T>>> {
+        let device_config = self.load_device_config(NotSet)?;
+
+        ByteBeamClient::start(
+            device_config.device_id,
+            device_config.project_id,
+            self.options,
+            self.extensions,
+            transport,
+            events,
+        )
+    }
+}
+
+/// Source used when there is no platform default to fall back to
+struct NotSet;
+
+impl ConfigSource for NotSet {
+    fn read(&mut self) -> Result<Vec<u8>> {
+        Err(ByteBeamError::ConfigNotFound(
+            "device config is not set".into(),
+        ))
+    }
+}
@@ -0,0 +1,808 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex, Weak},
+    thread,
+};
+
+use log::{error, info, warn};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+use crate::{
+    action::{
+        ActionContext, ActionOptions, ActionQueue, DeadlineAlarm, ResetHook, RunningAction,
+        TIMEOUT_CHECK_INTERVAL,
+    },
+    builder::{ByteBeamClientBuilder, ClientOptions, Extensions},
+    clock::{Clock, SystemClock},
+    connection::ConnectionState,
+    error::Result,
+    firmware,
+    journal::ActionJournal,
+    protocol::{self, Action, ActionStatus},
+    queue::{OfflineQueue, Record},
+    sequence::SequenceTracker,
+    shell::Command,
+    store::{BlobStore, JsonMap},
+    transport::{DefaultTransport, EventStream, Transport, TransportEvent},
+    ByteBeamError,
+};
+
+type ActionHandler<T> = Arc<Mutex<dyn FnMut(Action, ActionContext<T>) + Send>>;
+type ConnectionCallback<T> = Arc<dyn Fn(&ByteBeamClient<T>) + Send + Sync>;
+type ErrorCallback<T> = Arc<dyn Fn(&ByteBeamClient<T>, &ByteBeamError) + Send + Sync>;
+
+/// Client connected to Bytebeam cloud
+///
+/// Generic over the [`Transport`] used to reach the broker, which is the platform's MQTT client
+/// unless specified otherwise.
+pub struct ByteBeamClient<T: Transport = DefaultTransport> {
+    pub(crate) transport: Mutex<T>,
+    pub(crate) action_handles: Mutex<BTreeMap<String, ActionHandler<T>>>,
+    /// Handler of actions which have no handler of their own
+    pub(crate) fallback_action_handle: Mutex<Option<ActionHandler<T>>>,
+    pub(crate) action_options: Mutex<BTreeMap<String, ActionOptions>>,
+    /// Actions which are queued or running, by id
+    pub(crate) running_actions: Mutex<BTreeMap<String, RunningAction>>,
+    pub(crate) deadline_alarm: Arc<DeadlineAlarm>,
+    pub(crate) journal: Mutex<ActionJournal>,
+    pub(crate) clock: Arc<dyn Clock>,
+    pub(crate) reset_hook: Option<ResetHook>,
+    connection_state: Mutex<ConnectionState>,
+    on_connected: Mutex<Vec<ConnectionCallback<T>>>,
+    on_disconnected: Mutex<Vec<ConnectionCallback<T>>>,
+    on_error: Mutex<Vec<ErrorCallback<T>>>,
+    offline_queue: Option<Mutex<OfflineQueue>>,
+    pub(crate) sequences: SequenceTracker,
+    pub(crate) settings: Option<Mutex<JsonMap<Value>>>,
+    pub(crate) download_store: Option<Mutex<Box<dyn BlobStore>>>,
+    firmware_version: Option<String>,
+    pub(crate) commands: Mutex<BTreeMap<String, Command>>,
+    pub(crate) options: ClientOptions,
+    pub device_id: String,
+    pub project_id: String,
+}
+
+impl ByteBeamClient {
+    /// Create a builder for configuring client before connecting
+    ///
+    /// # Example
+    /// ```no_run
+    /// use bytebeam_esp_rs::ByteBeamClient;
+    ///
+    /// let bytebeam_client = ByteBeamClient::builder().client_id("sensor-1").connect()?;
+    /// # anyhow::Ok(())
+    /// ```
+    pub fn builder() -> ByteBeamClientBuilder {
+        ByteBeamClientBuilder::default()
+    }
+}
+
+impl<T: Transport> ByteBeamClient<T> {
+    /// Create Bytebeam Client on top of an already connected `transport`
+    ///
+    /// Spawns threads which listen on `events` and execute actions received from cloud.
+    /// Mostly useful for running against [`MockTransport`](crate::transport::MockTransport) in tests,
+    /// [`ByteBeamClient::connect`] should be used otherwise.
+    pub fn with_transport(
+        device_id: String,
+        project_id: String,
+        transport: T,
+        events: impl EventStream,
+    ) -> Result<Arc<Self>> {
+        Self::start(
+            device_id,
+            project_id,
+            ClientOptions::default(),
+            Extensions::default(),
+            transport,
+            events,
+        )
+    }
+
+    pub(crate) fn start(
+        device_id: String,
+        project_id: String,
+        options: ClientOptions,
+        extensions: Extensions,
+        transport: T,
+        mut events: impl EventStream,
+    ) -> Result<Arc<Self>> {
+        let actions_topic = protocol::actions_topic(&project_id, &device_id);
+
+        let action_handles = BTreeMap::new();
+        let offline_queue = options
+            .offline_queue
+            .as_ref()
+            .map(OfflineQueue::open)
+            .transpose()?
+            .map(Mutex::new);
+        let bytebeam_client = ByteBeamClient {
+            action_handles: Mutex::new(action_handles),
+            fallback_action_handle: Mutex::new(None),
+            action_options: Mutex::new(BTreeMap::new()),
+            running_actions: Mutex::new(BTreeMap::new()),
+            deadline_alarm: Arc::default(),
+            journal: Mutex::new(ActionJournal::open(
+                extensions.journal_store,
+                extensions.firmware_pending_verify,
+            )),
+            clock: extensions.clock.unwrap_or_else(|| Arc::new(SystemClock)),
+            reset_hook: extensions.reset_hook,
+            transport: Mutex::new(transport),
+            connection_state: Mutex::new(ConnectionState::Connecting),
+            on_connected: Mutex::new(Vec::new()),
+            on_disconnected: Mutex::new(Vec::new()),
+            on_error: Mutex::new(Vec::new()),
+            offline_queue,
+            sequences: SequenceTracker::new(extensions.sequence_store),
+            settings: extensions
+                .settings_store
+                .map(|store| Mutex::new(JsonMap::new("settings", store))),
+            download_store: extensions.download_store.map(Mutex::new),
+            firmware_version: options
+                .firmware_version
+                .clone()
+                .or_else(firmware::running_version),
+            commands: Mutex::new(BTreeMap::new()),
+            options,
+            device_id,
+            project_id,
+        };
+
+        let bytebeam_client = Arc::new(bytebeam_client);
+
+        let queue = Arc::new(ActionQueue::new(
+            bytebeam_client.options.action_queue_capacity,
+        ));
+        let cloned_queue = queue.clone();
+        let cloned_client = bytebeam_client.clone();
+        bytebeam_client
+            .options
+            .event_thread
+            .spawn("bytebeam-events", move || {
+                let bytebeam_client = cloned_client;
+                let queue = cloned_queue;
+                info!("MQTT Listening for messages");
+                let mut attempt = 0;
+                while let Some(message_event) = events.next_event() {
+                    match message_event {
+                        TransportEvent::Received { payload, .. } => {
+                            if let Ok(action) = serde_json::from_slice::<Action>(&payload) {
+                                bytebeam_client.dispatch_action(action, &queue);
+                            };
+                        }
+                        TransportEvent::Connected => {
+                            attempt = 0;
+                            // (re)subscribe to actions, session isn't persisted by broker
+                            let qos = bytebeam_client.options.actions_qos;
+                            match bytebeam_client
+                                .transport
+                                .lock()
+                                .unwrap()
+                                .subscribe(&actions_topic, qos)
+                            {
+                                Ok(_) => info!("subscribed to actions"),
+                                Err(e) => error!("Failed to subscribe to actions: {e}"),
+                            }
+                            bytebeam_client.set_connection_state(ConnectionState::Connected);
+                            bytebeam_client.drain_offline_queue();
+                            bytebeam_client.report_interrupted_actions();
+                            bytebeam_client.report_firmware_version();
+                            if bytebeam_client.options.advertise_actions {
+                                bytebeam_client.advertise_actions();
+                            }
+                        }
+                        TransportEvent::Disconnected => {
+                            info!("EVENT: {message_event:?}");
+                            bytebeam_client.set_connection_state(ConnectionState::Disconnected);
+                            bytebeam_client.reconnect(&mut attempt);
+                        }
+                        TransportEvent::Error(e) => {
+                            error!("MQTT error: {e}");
+                            bytebeam_client.notify_error(&e);
+                        }
+                    };
+                }
+
+                error!("MQTT connection loop exit");
+                bytebeam_client.set_connection_state(ConnectionState::Disconnected);
+                queue.close();
+                bytebeam_client.deadline_alarm.close();
+            })?;
+
+        // threads to execute actions
+        for _ in 0..bytebeam_client.options.action_workers.max(1) {
+            let cloned_client = bytebeam_client.clone();
+            let queue = queue.clone();
+            bytebeam_client
+                .options
+                .action_thread
+                .spawn("bytebeam-actions", move || {
+                    cloned_client.run_actions(&queue)
+                })?;
+        }
+
+        // thread enforcing deadlines of actions, idle while no action has one
+        let weak_client = Arc::downgrade(&bytebeam_client);
+        let alarm = bytebeam_client.deadline_alarm.clone();
+        bytebeam_client
+            .options
+            .event_thread
+            .spawn("bytebeam-timeouts", move || {
+                while let Some(bytebeam_client) = Weak::upgrade(&weak_client) {
+                    let has_deadlines = bytebeam_client.check_timeouts();
+                    drop(bytebeam_client);
+                    if has_deadlines {
+                        thread::sleep(TIMEOUT_CHECK_INTERVAL);
+                    } else if !alarm.wait() {
+                        return;
+                    }
+                }
+            })?;
+
+        Ok(bytebeam_client)
+    }
+
+    /// Version of running firmware, see [`ByteBeamClientBuilder::firmware_version`]
+    pub fn firmware_version(&self) -> Option<&str> {
+        self.firmware_version.as_deref()
+    }
+
+    /// Current state of connection with broker
+    pub fn connection_state(&self) -> ConnectionState {
+        *self.connection_state.lock().unwrap()
+    }
+
+    /// Call `callback` every time connection with broker is established
+    ///
+    /// Action subscription is already restored when `callback` runs. If client is connected at
+    /// the time of registering, `callback` is also called right away.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use bytebeam_esp_rs::ByteBeamClient;
+    ///
+    /// let bytebeam_client = ByteBeamClient::builder().connect()?;
+    /// bytebeam_client.on_connected(|_| log::info!("connected to Bytebeam"));
+    /// bytebeam_client.on_disconnected(|_| log::warn!("lost connection to Bytebeam"));
+    /// # anyhow::Ok(())
+    /// ```
+    pub fn on_connected(&self, callback: impl Fn(&Self) + Send + Sync + 'static) {
+        let callback: ConnectionCallback<T> = Arc::new(callback);
+        self.on_connected.lock().unwrap().push(callback.clone());
+        if self.connection_state() == ConnectionState::Connected {
+            callback(self);
+        }
+    }
+
+    /// Call `callback` every time an established connection with broker is lost
+    pub fn on_disconnected(&self, callback: impl Fn(&Self) + Send + Sync + 'static) {
+        self.on_disconnected
+            .lock()
+            .unwrap()
+            .push(Arc::new(callback));
+    }
+
+    /// Call `callback` with every error reported by transport, e.g. TLS handshake failures or
+    /// broker refusing connection
+    ///
+    /// Client keeps reconnecting on its own, this is for firmware that wants to react to errors,
+    /// e.g. by reprovisioning device once its certificates are rejected.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use bytebeam_esp_rs::{ByteBeamClient, ByteBeamError};
+    ///
+    /// let bytebeam_client = ByteBeamClient::builder().connect()?;
+    /// bytebeam_client.on_error(|_, e| match e {
+    ///     ByteBeamError::Tls(e) => log::error!("certificates rejected: {e}"),
+    ///     ByteBeamError::ConnectionRefused(e) => log::error!("broker refused connection: {e}"),
+    ///     _ => {}
+    /// });
+    /// # anyhow::Ok(())
+    /// ```
+    pub fn on_error(&self, callback: impl Fn(&Self, &ByteBeamError) + Send + Sync + 'static) {
+        self.on_error.lock().unwrap().push(Arc::new(callback));
+    }
+
+    fn notify_error(&self, error: &ByteBeamError) {
+        // cloned so that callbacks can register more callbacks
+        let callbacks = self.on_error.lock().unwrap().clone();
+        for callback in callbacks {
+            callback(self, error);
+        }
+    }
+
+    /// Update connection state, notifying callbacks of transitions to and from `Connected`
+    fn set_connection_state(&self, state: ConnectionState) {
+        let previous = std::mem::replace(&mut *self.connection_state.lock().unwrap(), state);
+        let callbacks = match (previous, state) {
+            (ConnectionState::Connected, ConnectionState::Connected) => return,
+            (_, ConnectionState::Connected) => &self.on_connected,
+            (ConnectionState::Connected, _) => &self.on_disconnected,
+            _ => return,
+        };
+
+        // cloned so that callbacks can register more callbacks
+        let callbacks = callbacks.lock().unwrap().clone();
+        for callback in callbacks {
+            callback(self);
+        }
+    }
+
+    /// Wait for backoff delay of `attempt` and ask transport to reconnect, retrying till it agrees
+    fn reconnect(&self, attempt: &mut u32) {
+        loop {
+            let delay = self.options.reconnect_backoff.delay(*attempt);
+            *attempt = attempt.saturating_add(1);
+            info!("Reconnecting in {delay:?}");
+            thread::sleep(delay);
+
+            self.set_connection_state(ConnectionState::Connecting);
+            match self.transport.lock().unwrap().reconnect() {
+                Ok(()) => return,
+                Err(e) => {
+                    error!("Failed to reconnect: {e}");
+                    self.set_connection_state(ConnectionState::Disconnected);
+                }
+            }
+        }
+    }
+
+    /// Publish data to stream, with next sequence number of stream
+    ///
+    /// See [`publish_to_stream`](Self::publish_to_stream) for details.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use bytebeam_esp_rs::ByteBeamClient;
+    ///
+    /// let bytebeam_client = ByteBeamClient::builder().connect()?;
+    /// bytebeam_client.publish("example_stream", serde_json::json!({ "status": "ON" }))?;
+    /// # anyhow::Ok(())
+    /// ```
+    pub fn publish(&self, stream_name: &str, payload: impl Serialize) -> Result<u32> {
+        let sequence = self.next_sequence(stream_name, 1);
+        self.publish_to_stream(stream_name, sequence, payload)
+    }
+
+    /// Reserve `count` consecutive sequence numbers of stream, returning the first one
+    ///
+    /// Useful for mixing explicit sequences with the ones managed by client. Panics if `count`
+    /// is `0`.
+    pub fn next_sequence(&self, stream_name: &str, count: u32) -> u32 {
+        self.sequences.next(stream_name, count)
+    }
+
+    /// Publish data to stream
+    ///
+    /// Payload should be a JSON array which must have `id`, `sequence` and `timestamp` fields
+    /// followed by any other fields defined by user
+    ///
+    /// With an [`offline_queue`](crate::ByteBeamClientBuilder::offline_queue), payload is held in
+    /// queue while client is offline, or transport can't take more messages, and `0` is returned
+    /// in place of message id. Queued data is sent in order once connection is back. Fails with
+    /// [`ByteBeamError::RecordsDropped`] if queue was full and records had to be dropped.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use bytebeam_esp_rs::ByteBeamClient;
+    /// # use serde::Serialize;
+    ///
+    /// #[derive(Serialize)]
+    /// struct MyStream {
+    ///     // your custom fields!
+    ///     status: String,
+    /// }
+    ///
+    /// let bytebeam_client = ByteBeamClient::builder().connect()?;
+    ///
+    /// let sequence = 1;
+    /// let message = MyStream {
+    ///     status: "ON".into(),
+    /// };
+    ///
+    /// bytebeam_client.publish_to_stream("example_stream", sequence, message)?;
+    /// # anyhow::Ok(())
+    /// ```
+    pub fn publish_to_stream(
+        &self,
+        stream_name: &str,
+        sequence: u32,
+        payload: impl Serialize,
+    ) -> Result<u32> {
+        let publish_topic = protocol::stream_topic(&self.project_id, &self.device_id, stream_name);
+
+        let final_payload = protocol::stream_records(&self.device_id, sequence, [payload])?;
+
+        self.publish_stream_data(&publish_topic, &final_payload)
+    }
+
+    /// Publish many records to stream in a single message
+    ///
+    /// Records get consecutive sequence numbers starting at `sequence`, wrapping around at
+    /// `u32::MAX`, and the same timestamp.
+    /// Use [`StreamBatcher`](crate::StreamBatcher) for collecting records over time instead.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use bytebeam_esp_rs::ByteBeamClient;
+    /// use serde::Serialize;
+    ///
+    /// #[derive(Serialize)]
+    /// struct Reading {
+    ///     temperature: f32,
+    /// }
+    ///
+    /// let bytebeam_client = ByteBeamClient::builder().connect()?;
+    /// let readings = [Reading { temperature: 21.5 }, Reading { temperature: 21.7 }];
+    /// bytebeam_client.publish_batch("temperature", 1, &readings)?;
+    /// # anyhow::Ok(())
+    /// ```
+    pub fn publish_batch<P: Serialize>(
+        &self,
+        stream_name: &str,
+        sequence: u32,
+        payloads: &[P],
+    ) -> Result<u32> {
+        let publish_topic = protocol::stream_topic(&self.project_id, &self.device_id, stream_name);
+
+        let final_payload = protocol::stream_records(&self.device_id, sequence, payloads)?;
+
+        self.publish_stream_data(&publish_topic, &final_payload)
+    }
+
+    /// Publish an already serialized JSON array of stream records, going through offline queue
+    ///
+    /// Payload is only copied if it has to be queued.
+    pub(crate) fn publish_stream_data(
+        &self,
+        publish_topic: &str,
+        final_payload: &[u8],
+    ) -> Result<u32> {
+        let Some(offline_queue) = &self.offline_queue else {
+            return self.transport.lock().unwrap().publish(
+                publish_topic,
+                self.options.stream_qos,
+                final_payload,
+            );
+        };
+
+        let mut offline_queue = offline_queue.lock().unwrap();
+        // going around queue would reorder data
+        if offline_queue.is_empty() && self.connection_state() == ConnectionState::Connected {
+            match self.transport.lock().unwrap().publish(
+                publish_topic,
+                self.options.stream_qos,
+                final_payload,
+            ) {
+                Ok(id) => return Ok(id),
+                Err(e) => warn!("Failed to publish, queueing for later: {e}"),
+            }
+        }
+
+        let dropped = offline_queue.push(Record {
+            topic: publish_topic.into(),
+            payload: final_payload.to_vec(),
+        });
+        drop(offline_queue);
+        self.drain_offline_queue();
+
+        if dropped > 0 {
+            return Err(ByteBeamError::RecordsDropped(dropped));
+        }
+        Ok(0)
+    }
+
+    /// Publish records queued while offline, in order, till transport stops accepting them
+    fn drain_offline_queue(&self) {
+        let Some(offline_queue) = &self.offline_queue else {
+            return;
+        };
+
+        let mut offline_queue = offline_queue.lock().unwrap();
+        while self.connection_state() == ConnectionState::Connected {
+            let Some(record) = offline_queue.front() else {
+                return;
+            };
+            let published = self.transport.lock().unwrap().publish(
+                &record.topic,
+                self.options.stream_qos,
+                &record.payload,
+            );
+            if published.is_err() {
+                // retried on next publish or reconnect
+                return;
+            }
+            offline_queue.pop();
+        }
+    }
+
+    /// Register a action handler
+    ///
+    /// `action_function` will get called when we receive an action with `action_name` from cloud,
+    /// replacing handler registered earlier for it, if any.
+    ///
+    /// `action_function` must take `Action` and [`ActionContext`] as arguments, and can own any
+    /// state it needs, e.g. drivers of peripherals. Status of action is reported through context.
+    ///
+    /// A panic in `action_function` is reported as failure of action where panics unwind, e.g. on
+    /// a host. ESP-IDF firmware is built with `panic_abort` though, so there a panic restarts
+    /// device and action is only reported as failed after reboot, if a persistent
+    /// [journal store](crate::ByteBeamClientBuilder::journal_store) is set.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use bytebeam_esp_rs::{Action, ActionContext, ByteBeamClient};
+    /// # struct Led;
+    /// # impl Led {
+    /// #     fn toggle(&mut self) -> anyhow::Result<()> { Ok(()) }
+    /// # }
+    /// # let mut onboard_led = Led;
+    ///
+    /// // `onboard_led` is e.g. `PinDriver::output(peripherals.pins.gpio2)?`
+    /// let bytebeam_client = ByteBeamClient::builder().connect()?;
+    /// bytebeam_client.register_action_handle(
+    ///     "toggle".into(),
+    ///     move |_action: Action, ctx: ActionContext| {
+    ///         match onboard_led.toggle() {
+    ///             Ok(_) => ctx.complete(),
+    ///             Err(_) => ctx.fail(&["Failed to toggle LED"]),
+    ///         }
+    ///         .ok(); // just to satisfy clippy for now!
+    ///     },
+    /// );
+    /// # anyhow::Ok(())
+    /// ```
+    /// Functions which take the same arguments work as well
+    ///
+    /// ```no_run
+    /// # use bytebeam_esp_rs::{Action, ActionContext, ByteBeamClient};
+    /// # let bytebeam_client = ByteBeamClient::builder().connect()?;
+    /// fn reboot(action: Action, ctx: ActionContext) {
+    ///     // function body here!
+    ///     // ...
+    /// }
+    ///
+    /// bytebeam_client.register_action_handle("reboot".into(), reboot);
+    /// # anyhow::Ok(())
+    /// ```
+    pub fn register_action_handle(
+        &self,
+        action_name: String,
+        action_function: impl FnMut(Action, ActionContext<T>) + Send + 'static,
+    ) {
+        info!("setting action handler for {action_name}");
+        self.action_handles
+            .lock()
+            .unwrap()
+            .insert(action_name, Arc::new(Mutex::new(action_function)));
+    }
+
+    /// Register a handler of `action_name` whose payload is JSON of type `P`
+    ///
+    /// Payload is deserialized before `action_function` gets called, and action is reported as
+    /// failed with the serde error if it doesn't match `P`. Actions without payload are parsed as
+    /// `null`, so `P` can be an `Option` or `()` for them.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use bytebeam_esp_rs::{ActionContext, ByteBeamClient};
+    /// use serde::Deserialize;
+    ///
+    /// #[derive(Deserialize)]
+    /// struct Brightness {
+    ///     level: u8,
+    /// }
+    ///
+    /// let bytebeam_client = ByteBeamClient::builder().connect()?;
+    /// bytebeam_client.register_typed_action(
+    ///     "set_brightness".into(),
+    ///     |brightness: Brightness, ctx: ActionContext| {
+    ///         println!("setting brightness to {}", brightness.level);
+    ///         ctx.complete().ok();
+    ///     },
+    /// );
+    /// # anyhow::Ok(())
+    /// ```
+    pub fn register_typed_action<P: DeserializeOwned>(
+        &self,
+        action_name: String,
+        mut action_function: impl FnMut(P, ActionContext<T>) + Send + 'static,
+    ) {
+        self.register_action_handle(action_name, move |action: Action, ctx: ActionContext<T>| {
+            let payload = action.payload.as_deref().unwrap_or("null");
+            match serde_json::from_str(payload) {
+                Ok(payload) => action_function(payload, ctx),
+                Err(e) => {
+                    error!("Invalid payload for action {}: {e}", action.name);
+                    ctx.fail(&[&format!("invalid payload: {e}")]).ok();
+                }
+            }
+        })
+    }
+
+    /// Remove handler of `action_name`, returning whether there was one
+    ///
+    /// Handler which is currently running isn't interrupted, and is dropped once it returns.
+    pub fn unregister_action_handle(&self, action_name: &str) -> bool {
+        info!("removing action handler for {action_name}");
+        self.action_handles
+            .lock()
+            .unwrap()
+            .remove(action_name)
+            .is_some()
+    }
+
+    /// Set limits on execution of `action_name`, replacing ones set earlier
+    ///
+    /// Applies to actions which start running after the call, whichever way they are handled.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use bytebeam_esp_rs::{ActionOptions, ByteBeamClient};
+    ///
+    /// let bytebeam_client = ByteBeamClient::builder().connect()?;
+    /// bytebeam_client.set_action_options(
+    ///     "update_firmware",
+    ///     ActionOptions {
+    ///         timeout: Some(Duration::from_secs(10 * 60)),
+    ///         reset_after: Some(Duration::from_secs(60)),
+    ///         priority: 10,
+    ///     },
+    /// );
+    /// # anyhow::Ok(())
+    /// ```
+    pub fn set_action_options(&self, action_name: &str, options: ActionOptions) {
+        self.action_options
+            .lock()
+            .unwrap()
+            .insert(action_name.into(), options);
+    }
+
+    /// Run `action_name` with `priority`, unless options were already set for it
+    pub(crate) fn default_action_priority(&self, action_name: &str, priority: u8) {
+        self.action_options
+            .lock()
+            .unwrap()
+            .entry(action_name.into())
+            .or_insert(ActionOptions {
+                priority,
+                ..Default::default()
+            });
+    }
+
+    /// Handle actions for which no handler is registered with `action_function`
+    ///
+    /// Without a fallback handler, such actions are reported as failed with an
+    /// "unsupported action" error.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use bytebeam_esp_rs::{Action, ActionContext, ByteBeamClient};
+    ///
+    /// let bytebeam_client = ByteBeamClient::builder().connect()?;
+    /// bytebeam_client.register_fallback_action_handle(|action: Action, ctx: ActionContext| {
+    ///     log::warn!("forwarding {} to co-processor", action.name);
+    ///     // ...
+    ///     ctx.complete().ok();
+    /// });
+    /// # anyhow::Ok(())
+    /// ```
+    pub fn register_fallback_action_handle(
+        &self,
+        action_function: impl FnMut(Action, ActionContext<T>) + Send + 'static,
+    ) {
+        info!("setting fallback action handler");
+        *self.fallback_action_handle.lock().unwrap() = Some(Arc::new(Mutex::new(action_function)));
+    }
+
+    /// Remove fallback handler, returning whether there was one
+    pub fn unregister_fallback_action_handle(&self) -> bool {
+        info!("removing fallback action handler");
+        self.fallback_action_handle.lock().unwrap().take().is_some()
+    }
+
+    /// Publish the action status to cloud
+    ///
+    /// Handlers should report status through [`ActionContext`] instead, this is meant for states
+    /// other than [`ActionState`](crate::ActionState)s, or actions tracked outside a handler.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use bytebeam_esp_rs::ByteBeamClient;
+    /// let bytebeam_client = ByteBeamClient::builder().connect()?;
+    /// bytebeam_client.publish_action_status("42", 50, "Downloading", None)?;
+    /// # anyhow::Ok(())
+    /// ```
+    pub fn publish_action_status(
+        &self,
+        action_id: &str,
+        percentage: u32,
+        status: &str,
+        error_messages: Option<&[&str]>,
+    ) -> Result<u32> {
+        let publish_topic = protocol::action_status_topic(&self.project_id, &self.device_id);
+
+        let errors = error_messages.unwrap_or(&[]);
+        let timestamp = protocol::timestamp();
+
+        let action_status = ActionStatus {
+            id: action_id,
+            errors,
+            progress: percentage,
+            state: status,
+            timestamp,
+        };
+
+        let action_status = [action_status];
+
+        // NOTE: convert to string if we want to log it
+        // let payload = serde_json::to_string(&action_status)?;
+        // println!("status payload: {payload}");
+
+        let payload = serde_json::to_vec(&action_status).map_err(ByteBeamError::Serialization)?;
+        self.transport.lock().unwrap().publish(
+            &publish_topic,
+            self.options.action_status_qos,
+            &payload,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        queue::DropPolicy,
+        transport::{
+            testing::{device_config, stream_messages, wait_for},
+            MockTransport,
+        },
+        OfflineQueueOptions,
+    };
+
+    #[test]
+    fn publish_reports_records_dropped_while_offline() {
+        let (transport, events, broker) = MockTransport::new();
+        let client = ByteBeamClient::builder()
+            .device_config(device_config())
+            .offline_queue(OfflineQueueOptions {
+                ram_capacity: 2,
+                spill: None,
+                drop_policy: DropPolicy::Newest,
+            })
+            .connect_with(transport, events)
+            .unwrap();
+
+        assert_eq!(
+            client
+                .publish("temperature", json!({ "value": 1 }))
+                .unwrap(),
+            0
+        );
+        assert_eq!(
+            client
+                .publish("temperature", json!({ "value": 2 }))
+                .unwrap(),
+            0
+        );
+        assert!(matches!(
+            client.publish("temperature", json!({ "value": 3 })),
+            Err(ByteBeamError::RecordsDropped(1))
+        ));
+
+        broker.connect();
+        wait_for(|| stream_messages(&broker, "temperature").len() == 2);
+        let values: Vec<_> = stream_messages(&broker, "temperature")
+            .into_iter()
+            .map(|records| records[0]["value"].clone())
+            .collect();
+        assert_eq!(values, [1, 2]);
+    }
+}
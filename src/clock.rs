@@ -0,0 +1,93 @@
+//! Source of time for deadlines of actions and batches
+//!
+//! Client reads time through the [`Clock`] trait, so that timeouts can be tested on host with
+//! [`MockClock`] instead of waiting for them.
+//!
+//! # Example
+//! ```no_run
+//! use std::time::Duration;
+//! use bytebeam_esp_rs::{
+//!     clock::MockClock, transport::MockTransport, ActionOptions, ByteBeamClient, DeviceConfig,
+//! };
+//!
+//! # let device_config = DeviceConfig::from_slice(br#"{
+//! #     "project_id": "demo",
+//! #     "broker": "localhost",
+//! #     "port": 1883,
+//! #     "device_id": "1",
+//! #     "authentication": {
+//! #         "ca_certificate": "",
+//! #         "device_certificate": "",
+//! #         "device_private_key": ""
+//! #     }
+//! # }"#)?;
+//! let clock = MockClock::new();
+//! let (transport, events, broker) = MockTransport::new();
+//! let client = ByteBeamClient::builder()
+//!     .device_config(device_config)
+//!     .clock(clock.clone())
+//!     .connect_with(transport, events)?;
+//! client.set_action_options(
+//!     "calibrate",
+//!     ActionOptions {
+//!         timeout: Some(Duration::from_secs(30)),
+//!         ..Default::default()
+//!     },
+//! );
+//!
+//! // ... send "calibrate" action through `broker`
+//! clock.advance(Duration::from_secs(31));
+//! # anyhow::Ok(())
+//! ```
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Source of monotonic time
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+}
+
+/// Time of the system, used by default
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Clock which only moves when told to, clones share the same time
+#[derive(Clone, Debug)]
+pub struct MockClock {
+    start: Instant,
+    elapsed: Arc<Mutex<Duration>>,
+}
+
+impl MockClock {
+    pub fn new() -> Self {
+        MockClock {
+            start: Instant::now(),
+            elapsed: Arc::new(Mutex::new(Duration::ZERO)),
+        }
+    }
+
+    /// Move time forward by `duration`
+    pub fn advance(&self, duration: Duration) {
+        *self.elapsed.lock().unwrap() += duration;
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.start + *self.elapsed.lock().unwrap()
+    }
+}
@@ -0,0 +1,245 @@
+//! Connection supervision: state of connection with broker and backoff between reconnects
+use std::{
+    sync::atomic::{AtomicU32, Ordering},
+    time::Duration,
+};
+
+/// State of connection with broker
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// Waiting for broker to accept connection, either for the first time or after a reconnect
+    Connecting,
+    /// Connected with broker, and subscribed to actions
+    Connected,
+    /// Connection was lost, waiting before reconnecting
+    Disconnected,
+}
+
+/// Exponential backoff between reconnection attempts
+///
+/// Delay before `n`th attempt is `initial * multiplier^n`, capped at `max`, with up to `jitter`
+/// fraction of it randomly added or removed so that a fleet of devices doesn't reconnect in
+/// lockstep after a broker restart.
+///
+/// # Example
+/// ```
+/// use std::time::Duration;
+/// use bytebeam_esp_rs::Backoff;
+///
+/// let backoff = Backoff {
+///     initial: Duration::from_millis(100),
+///     max: Duration::from_secs(1),
+///     multiplier: 2,
+///     jitter: 0.0,
+/// };
+///
+/// assert_eq!(backoff.delay(0), Duration::from_millis(100));
+/// assert_eq!(backoff.delay(3), Duration::from_millis(800));
+/// assert_eq!(backoff.delay(10), Duration::from_secs(1));
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct Backoff {
+    /// Delay before first reconnection attempt
+    pub initial: Duration,
+    /// Upper bound of delay
+    pub max: Duration,
+    /// Factor by which delay grows after every failed attempt
+    pub multiplier: u32,
+    /// Fraction of delay, between `0.0` and `1.0`, that is randomized
+    pub jitter: f32,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(60),
+            multiplier: 2,
+            jitter: 0.2,
+        }
+    }
+}
+
+impl Backoff {
+    /// Delay before reconnection attempt number `attempt`, counting from `0`
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let delay = self
+            .multiplier
+            .checked_pow(attempt)
+            .and_then(|factor| self.initial.checked_mul(factor))
+            .map_or(self.max, |delay| delay.min(self.max));
+
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        if jitter == 0.0 {
+            return delay;
+        }
+
+        // uniformly distributed in [1 - jitter, 1 + jitter]
+        let factor = 1.0 + jitter * (2.0 * random() - 1.0);
+        delay.mul_f32(factor)
+    }
+}
+
+/// Pseudo random number in `[0, 1)`, good enough for spreading out reconnects
+fn random() -> f32 {
+    static STATE: AtomicU32 = AtomicU32::new(0);
+
+    let mut x = STATE.load(Ordering::Relaxed);
+    if x == 0 {
+        x = crate::protocol::timestamp() as u32 | 1;
+    }
+    // xorshift32
+    x ^= x << 13;
+    x ^= x >> 17;
+    x ^= x << 5;
+    STATE.store(x, Ordering::Relaxed);
+
+    (x >> 8) as f32 / (1 << 24) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Instant,
+    };
+
+    use super::*;
+    use crate::{
+        transport::{
+            testing::{device_config, wait_for, ACTIONS_TOPIC},
+            MockTransport,
+        },
+        ByteBeamClient,
+    };
+
+    const BACKOFF: Backoff = Backoff {
+        initial: Duration::from_millis(20),
+        max: Duration::from_millis(80),
+        multiplier: 2,
+        jitter: 0.0,
+    };
+
+    #[test]
+    fn delay_grows_till_max() {
+        let delays: Vec<_> = (0..5).map(|attempt| BACKOFF.delay(attempt)).collect();
+        assert_eq!(delays, [20, 40, 80, 80, 80].map(Duration::from_millis));
+        assert_eq!(BACKOFF.delay(u32::MAX), BACKOFF.max);
+    }
+
+    #[test]
+    fn jitter_stays_in_bounds() {
+        let backoff = Backoff {
+            jitter: 0.5,
+            ..BACKOFF
+        };
+        for _ in 0..1000 {
+            let delay = backoff.delay(0);
+            assert!(delay >= Duration::from_millis(10), "{delay:?}");
+            assert!(delay <= Duration::from_millis(30), "{delay:?}");
+        }
+    }
+
+    #[test]
+    fn reconnects_and_resubscribes_after_disconnect() {
+        let (transport, events, broker) = MockTransport::new();
+        let client = ByteBeamClient::builder()
+            .device_config(device_config())
+            .reconnect_backoff(BACKOFF)
+            .connect_with(transport, events)
+            .unwrap();
+
+        let connected = Arc::new(AtomicUsize::new(0));
+        let disconnected = Arc::new(AtomicUsize::new(0));
+        let counter = connected.clone();
+        client.on_connected(move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        let counter = disconnected.clone();
+        client.on_disconnected(move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+
+        broker.connect();
+        wait_for(|| client.connection_state() == ConnectionState::Connected);
+        assert_eq!(broker.subscriptions(), [ACTIONS_TOPIC]);
+        assert_eq!(connected.load(Ordering::SeqCst), 1);
+
+        broker.disconnect();
+        wait_for(|| broker.reconnect_attempts().len() == 1);
+        wait_for(|| client.connection_state() == ConnectionState::Connected);
+        assert_eq!(broker.subscriptions(), [ACTIONS_TOPIC]);
+        assert_eq!(connected.load(Ordering::SeqCst), 2);
+        assert_eq!(disconnected.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn backs_off_between_failed_reconnects() {
+        let (transport, events, broker) = MockTransport::new();
+        let client = ByteBeamClient::builder()
+            .device_config(device_config())
+            .reconnect_backoff(BACKOFF)
+            .connect_with(transport, events)
+            .unwrap();
+        let disconnected = Arc::new(AtomicUsize::new(0));
+        let counter = disconnected.clone();
+        client.on_disconnected(move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+
+        broker.connect();
+        wait_for(|| client.connection_state() == ConnectionState::Connected);
+
+        broker.fail_reconnects(3);
+        let lost = Instant::now();
+        broker.disconnect();
+        wait_for(|| broker.reconnect_attempts().len() == 4);
+        wait_for(|| client.connection_state() == ConnectionState::Connected);
+        assert_eq!(broker.subscriptions(), [ACTIONS_TOPIC]);
+        // failed attempts never reached `Connected`, so are not reported as disconnections
+        assert_eq!(disconnected.load(Ordering::SeqCst), 1);
+
+        let attempts = broker.reconnect_attempts();
+        let mut previous = lost;
+        for (attempt, at) in attempts.into_iter().enumerate() {
+            let waited = at - previous;
+            assert!(
+                waited >= BACKOFF.delay(attempt as u32),
+                "attempt {attempt} after {waited:?}"
+            );
+            previous = at;
+        }
+    }
+
+    #[test]
+    fn backoff_restarts_after_successful_reconnect() {
+        let backoff = Backoff {
+            max: Duration::from_secs(2),
+            multiplier: 10,
+            ..BACKOFF
+        };
+        let (transport, events, broker) = MockTransport::new();
+        let client = ByteBeamClient::builder()
+            .device_config(device_config())
+            .reconnect_backoff(backoff)
+            .connect_with(transport, events)
+            .unwrap();
+        broker.connect();
+        wait_for(|| client.connection_state() == ConnectionState::Connected);
+
+        broker.fail_reconnects(1);
+        broker.disconnect();
+        wait_for(|| broker.reconnect_attempts().len() == 2);
+        wait_for(|| client.connection_state() == ConnectionState::Connected);
+
+        let lost = Instant::now();
+        broker.disconnect();
+        wait_for(|| broker.reconnect_attempts().len() == 3);
+        let waited = broker.reconnect_attempts()[2] - lost;
+        assert!(waited >= backoff.initial, "{waited:?}");
+        assert!(waited < backoff.delay(1), "{waited:?}");
+    }
+}
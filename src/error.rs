@@ -0,0 +1,128 @@
+//! Errors returned by the SDK
+use std::{error::Error, fmt, io};
+
+/// Boxed error coming from underlying platform or library
+pub type BoxError = Box<dyn Error + Send + Sync>;
+
+/// Result type used throughout the SDK
+pub type Result<T, E = ByteBeamError> = std::result::Result<T, E>;
+
+/// Errors returned by the SDK
+///
+/// Converts into [`anyhow::Error`] with `?`, and [`anyhow::Error`] converts into
+/// [`ByteBeamError::Other`].
+///
+/// # Example
+/// ```no_run
+/// use bytebeam_esp_rs::{ByteBeamClient, ByteBeamError};
+///
+/// match ByteBeamClient::builder().connect() {
+///     Ok(client) => { /* ... */ }
+///     Err(ByteBeamError::ConfigNotFound(e)) => println!("device is not provisioned: {e}"),
+///     Err(e) => println!("failed to connect: {e}"),
+/// }
+/// ```
+#[derive(Debug)]
+pub enum ByteBeamError {
+    /// Device config couldn't be read from its source
+    ConfigNotFound(BoxError),
+    /// Device config isn't valid JSON, or some fields are missing
+    InvalidConfig(serde_json::Error),
+    /// Certificates are invalid, or TLS handshake failed
+    Tls(BoxError),
+    /// Broker couldn't be reached, or it refused the connection
+    ConnectionRefused(BoxError),
+    /// Outgoing queue of MQTT client is full, message was not published
+    QueueFull,
+    /// Offline queue was full, so this many records were dropped: queued ones with
+    /// [`DropPolicy::Oldest`](crate::DropPolicy::Oldest), the one being published with
+    /// [`DropPolicy::Newest`](crate::DropPolicy::Newest)
+    RecordsDropped(usize),
+    /// Any other error from MQTT client
+    Transport(BoxError),
+    /// Payload couldn't be serialized to JSON
+    Serialization(serde_json::Error),
+    /// Over the air update failed
+    Ota(BoxError),
+    /// Persistent storage, e.g. NVS, couldn't be read or written
+    Storage(BoxError),
+    /// System call failed, e.g. while spawning threads
+    Io(io::Error),
+    /// No command is registered under this name, see [`shell`](crate::shell)
+    UnknownCommand(String),
+    /// Command was given an argument it doesn't accept, with what is wrong about it
+    InvalidArgument(String),
+    /// Any other error, e.g. from application code
+    Other(anyhow::Error),
+}
+
+impl ByteBeamError {
+    pub(crate) fn ota(error: impl Into<BoxError>) -> Self {
+        ByteBeamError::Ota(error.into())
+    }
+
+    #[cfg(feature = "esp-idf")]
+    pub(crate) fn storage(error: impl Into<BoxError>) -> Self {
+        ByteBeamError::Storage(error.into())
+    }
+
+    #[cfg(any(feature = "esp-idf", feature = "std"))]
+    pub(crate) fn transport(error: impl Into<BoxError>) -> Self {
+        ByteBeamError::Transport(error.into())
+    }
+}
+
+impl fmt::Display for ByteBeamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ByteBeamError::ConfigNotFound(e) => write!(f, "device config not found: {e}"),
+            ByteBeamError::InvalidConfig(e) => write!(f, "invalid device config: {e}"),
+            ByteBeamError::Tls(e) => write!(f, "TLS error: {e}"),
+            ByteBeamError::ConnectionRefused(e) => write!(f, "connection refused: {e}"),
+            ByteBeamError::QueueFull => write!(f, "outgoing queue is full"),
+            ByteBeamError::RecordsDropped(n) => {
+                write!(f, "offline queue is full, dropped {n} records")
+            }
+            ByteBeamError::Transport(e) => write!(f, "MQTT error: {e}"),
+            ByteBeamError::Serialization(e) => write!(f, "failed to serialize payload: {e}"),
+            ByteBeamError::Ota(e) => write!(f, "OTA failed: {e}"),
+            ByteBeamError::Storage(e) => write!(f, "storage error: {e}"),
+            ByteBeamError::Io(e) => write!(f, "I/O error: {e}"),
+            ByteBeamError::UnknownCommand(name) => write!(f, "unknown command: {name}"),
+            ByteBeamError::InvalidArgument(e) => write!(f, "invalid argument: {e}"),
+            ByteBeamError::Other(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl Error for ByteBeamError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ByteBeamError::ConfigNotFound(e)
+            | ByteBeamError::Tls(e)
+            | ByteBeamError::ConnectionRefused(e)
+            | ByteBeamError::Transport(e)
+            | ByteBeamError::Ota(e)
+            | ByteBeamError::Storage(e) => Some(e.as_ref()),
+            ByteBeamError::InvalidConfig(e) | ByteBeamError::Serialization(e) => Some(e),
+            ByteBeamError::Io(e) => Some(e),
+            ByteBeamError::Other(e) => Some(e.as_ref()),
+            ByteBeamError::QueueFull
+            | ByteBeamError::RecordsDropped(_)
+            | ByteBeamError::UnknownCommand(_)
+            | ByteBeamError::InvalidArgument(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for ByteBeamError {
+    fn from(e: io::Error) -> Self {
+        ByteBeamError::Io(e)
+    }
+}
+
+impl From<anyhow::Error> for ByteBeamError {
+    fn from(e: anyhow::Error) -> Self {
+        ByteBeamError::Other(e)
+    }
+}
@@ -0,0 +1,213 @@
+use std::{
+    ffi::{CStr, CString},
+    fs, ptr,
+};
+
+use embedded_svc::storage::RawStorage;
+use esp_idf_svc::nvs::{EspNvs, EspNvsPartition, NvsPartitionId};
+use esp_idf_sys::{
+    esp_err_t, esp_err_to_name, esp_vfs_fat_mount_config_t, esp_vfs_spiffs_conf_t,
+    esp_vfs_spiffs_register, esp_vfs_unregister, wl_handle_t, ESP_OK, WL_INVALID_HANDLE,
+};
+
+use crate::{config::ConfigSource, error::Result, ByteBeamError};
+
+/// Config file stored in a SPIFFS partition
+///
+/// Partition is mounted only while reading config. Default is `/spiffs/device_config.json` in the
+/// first SPIFFS partition, which is where [provision app](https://github.com/bytebeamio/bytebeam-esp-rs-sdk/tree/main/tools/provision)
+/// puts it.
+pub struct Spiffs {
+    /// Label of partition, first SPIFFS partition is used if `None`
+    pub partition_label: Option<String>,
+    /// Path at which partition is mounted
+    pub base_path: String,
+    /// Name of config file inside partition
+    pub file_name: String,
+}
+
+impl Default for Spiffs {
+    fn default() -> Self {
+        Spiffs {
+            partition_label: None,
+            base_path: "/spiffs".into(),
+            file_name: "device_config.json".into(),
+        }
+    }
+}
+
+impl ConfigSource for Spiffs {
+    fn read(&mut self) -> Result<Vec<u8>> {
+        let base_path = CString::new(self.base_path.as_str()).map_err(not_found)?;
+        let partition_label = self
+            .partition_label
+            .as_deref()
+            .map(CString::new)
+            .transpose()
+            .map_err(not_found)?;
+        let configuration_spiffs = esp_vfs_spiffs_conf_t {
+            base_path: base_path.as_ptr(),
+            format_if_mount_failed: true,
+            max_files: 5,
+            partition_label: partition_label
+                .as_ref()
+                .map_or(ptr::null(), |label| label.as_ptr()),
+        };
+
+        unsafe {
+            let ret = esp_vfs_spiffs_register(&configuration_spiffs);
+
+            if ret != ESP_OK {
+                esp_vfs_unregister(configuration_spiffs.base_path);
+                return Err(not_found(format!(
+                    "failed to mount SPIFFS: {}",
+                    err_name(ret)
+                )));
+            }
+        }
+
+        let config = fs::read(format!("{}/{}", self.base_path, self.file_name));
+
+        unsafe {
+            esp_vfs_unregister(configuration_spiffs.base_path);
+        }
+
+        config.map_err(not_found)
+    }
+}
+
+/// Config file stored in a wear levelled FAT partition
+///
+/// Partition is mounted only while reading config.
+pub struct Fat {
+    /// Label of partition
+    pub partition_label: String,
+    /// Path at which partition is mounted
+    pub base_path: String,
+    /// Name of config file inside partition
+    pub file_name: String,
+}
+
+impl Default for Fat {
+    fn default() -> Self {
+        Fat {
+            partition_label: "storage".into(),
+            base_path: "/fat".into(),
+            file_name: "device_config.json".into(),
+        }
+    }
+}
+
+impl ConfigSource for Fat {
+    fn read(&mut self) -> Result<Vec<u8>> {
+        let base_path = CString::new(self.base_path.as_str()).map_err(not_found)?;
+        let partition_label = CString::new(self.partition_label.as_str()).map_err(not_found)?;
+        let mount_config = esp_vfs_fat_mount_config_t {
+            format_if_mount_failed: false,
+            max_files: 5,
+            ..Default::default()
+        };
+        let mut wl_handle: wl_handle_t = WL_INVALID_HANDLE;
+
+        let ret = unsafe {
+            #[cfg(esp_idf_version_major = "4")]
+            let ret = esp_idf_sys::esp_vfs_fat_spiflash_mount(
+                base_path.as_ptr(),
+                partition_label.as_ptr(),
+                &mount_config,
+                &mut wl_handle,
+            );
+            #[cfg(not(esp_idf_version_major = "4"))]
+            let ret = esp_idf_sys::esp_vfs_fat_spiflash_mount_rw_wl(
+                base_path.as_ptr(),
+                partition_label.as_ptr(),
+                &mount_config,
+                &mut wl_handle,
+            );
+            ret
+        };
+        if ret != ESP_OK {
+            return Err(not_found(format!("failed to mount FAT: {}", err_name(ret))));
+        }
+
+        let config = fs::read(format!("{}/{}", self.base_path, self.file_name));
+
+        unsafe {
+            #[cfg(esp_idf_version_major = "4")]
+            esp_idf_sys::esp_vfs_fat_spiflash_unmount(base_path.as_ptr(), wl_handle);
+            #[cfg(not(esp_idf_version_major = "4"))]
+            esp_idf_sys::esp_vfs_fat_spiflash_unmount_rw_wl(base_path.as_ptr(), wl_handle);
+        }
+
+        config.map_err(not_found)
+    }
+}
+
+/// Config stored as a blob in NVS
+///
+/// # Example
+/// ```no_run
+/// use bytebeam_esp_rs::{config::Nvs, ByteBeamClient};
+/// use esp_idf_svc::nvs::EspDefaultNvsPartition;
+///
+/// let nvs = EspDefaultNvsPartition::take()?;
+/// let bytebeam_client = ByteBeamClient::builder()
+///     .config_source(Nvs::new(nvs, "bytebeam", "config"))
+///     .connect()?;
+/// # anyhow::Ok(())
+/// ```
+pub struct Nvs<T: NvsPartitionId> {
+    partition: EspNvsPartition<T>,
+    namespace: String,
+    key: String,
+}
+
+impl<T: NvsPartitionId> Nvs<T> {
+    /// Config stored under `key` of `namespace` in `partition`
+    pub fn new(
+        partition: EspNvsPartition<T>,
+        namespace: impl Into<String>,
+        key: impl Into<String>,
+    ) -> Self {
+        Nvs {
+            partition,
+            namespace: namespace.into(),
+            key: key.into(),
+        }
+    }
+
+    /// Store `config` in NVS, e.g. when provisioning device over serial
+    pub fn write(&mut self, config: &[u8]) -> Result<()> {
+        let mut nvs = EspNvs::new(self.partition.clone(), &self.namespace, true)
+            .map_err(ByteBeamError::storage)?;
+        RawStorage::set_raw(&mut nvs, &self.key, config).map_err(ByteBeamError::storage)?;
+        Ok(())
+    }
+}
+
+impl<T: NvsPartitionId> ConfigSource for Nvs<T> {
+    fn read(&mut self) -> Result<Vec<u8>> {
+        let nvs = EspNvs::new(self.partition.clone(), &self.namespace, false).map_err(not_found)?;
+        let len = RawStorage::len(&nvs, &self.key)
+            .map_err(not_found)?
+            .ok_or_else(|| not_found(format!("{} not found in NVS", self.key)))?;
+
+        let mut config = vec![0; len];
+        let read = RawStorage::get_raw(&nvs, &self.key, &mut config)
+            .map_err(not_found)?
+            .map_or(0, |c| c.len());
+        config.truncate(read);
+
+        Ok(config)
+    }
+}
+
+fn not_found(e: impl Into<crate::error::BoxError>) -> ByteBeamError {
+    ByteBeamError::ConfigNotFound(e.into())
+}
+
+fn err_name(err: esp_err_t) -> String {
+    unsafe { CStr::from_ptr(esp_err_to_name(err)) }
+        .to_string_lossy()
+        .into_owned()
+}
@@ -0,0 +1,213 @@
+//! ESP-IDF specific parts of the SDK
+use std::sync::Arc;
+
+use esp_idf_hal::task::thread::ThreadSpawnConfiguration;
+use esp_idf_svc::{mqtt::client::MqttClientConfiguration, tls::X509};
+use log::warn;
+
+use crate::{
+    builder::ByteBeamClientBuilder, error::Result, firmware::OtaOptions, ActionOptions,
+    ByteBeamClient, DeviceConfig,
+};
+
+pub(crate) mod config;
+mod ota;
+pub(crate) mod rollback;
+pub(crate) mod store;
+pub(crate) mod system;
+mod transport;
+
+pub use transport::EspMqttTransport;
+
+impl ByteBeamClient<EspMqttTransport> {
+    /// Initialze Bytebeam Client
+    ///
+    /// This will read `spiffs/device_config.json` config file and try to connect with Bytebeam cloud.
+    /// Spawns a MQTT client to communicate with cloud internally
+    ///
+    /// Make sure `spiffs/device_config.json` file is present in SPIFFS before calling this.
+    /// You can use [provision app](https://github.com/bytebeamio/bytebeam-esp-rs-sdk/tree/main/tools/provision) to flash the config file
+    ///
+    /// Use [`ByteBeamClient::builder`] for more control over how client is set up.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use bytebeam_esp_rs::ByteBeamClient;
+    ///
+    /// let bytebeam_client = ByteBeamClient::init();
+    /// ```
+    pub fn init() -> Result<Arc<Self>> {
+        Self::builder().connect()
+    }
+
+    /// Connect with Bytebeam cloud using given `device_config`
+    pub fn connect(device_config: DeviceConfig) -> Result<Arc<Self>> {
+        Self::builder().device_config(device_config).connect()
+    }
+
+    /// Enable Over The Air firmware updates
+    ///
+    /// This will register "update_firmware" action to a OTA handler. Action is reported as
+    /// completed once device boots with new firmware if a persistent
+    /// [`journal_store`](ByteBeamClientBuilder::journal_store) is set, right before resetting
+    /// into it otherwise. See [`ByteBeamClientBuilder::firmware_health_check`] for rolling back
+    /// firmware which doesn't work. Updates fail while firmware installed by the previous one is
+    /// yet to pass that check. Updates run with [`ActionOptions::SYSTEM_PRIORITY`], unless
+    /// options were set for "update_firmware" already.
+    pub fn enable_ota(&self) {
+        self.enable_ota_with(OtaOptions::default())
+    }
+
+    /// Enable Over The Air firmware updates, with images checked as required by `options`
+    ///
+    /// # Example
+    /// ```no_run
+    /// use bytebeam_esp_rs::{firmware::OtaOptions, ByteBeamClient};
+    ///
+    /// let bytebeam_client = ByteBeamClient::init()?;
+    /// bytebeam_client.enable_ota_with(OtaOptions {
+    ///     require_checksum: true,
+    ///     ..Default::default()
+    /// });
+    /// # anyhow::Ok(())
+    /// ```
+    pub fn enable_ota_with(&self, options: OtaOptions) {
+        // register firmware update action handler
+        self.register_typed_action("update_firmware".into(), move |payload, ctx| {
+            ota::handle_ota(payload, ctx, &options)
+        });
+        self.default_action_priority("update_firmware", ActionOptions::SYSTEM_PRIORITY);
+    }
+}
+
+impl ByteBeamClientBuilder {
+    /// Check new firmware with `health_check` after an OTA update, before marking it valid
+    ///
+    /// Needs `CONFIG_BOOTLOADER_APP_ROLLBACK_ENABLE`, with which firmware booted after an update
+    /// is rolled back on next reset unless marked valid. Client marks it valid once it connects
+    /// with broker and `health_check` passes, within
+    /// [`firmware_check_timeout`](crate::ClientOptions::firmware_check_timeout). Otherwise update
+    /// action is reported as failed and device reboots into previous firmware. Without a
+    /// `health_check`, connecting with broker is enough.
+    ///
+    /// Reporting the outcome needs a persistent [`journal_store`](Self::journal_store), which
+    /// remembers update action across the reset into new firmware. Without one, update is
+    /// reported as completed before the reset, and a roll back goes unreported.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use bytebeam_esp_rs::{store, ByteBeamClient};
+    /// use esp_idf_svc::nvs::EspDefaultNvsPartition;
+    ///
+    /// let nvs = EspDefaultNvsPartition::take()?;
+    /// let bytebeam_client = ByteBeamClient::builder()
+    ///     .journal_store(store::Nvs::new(nvs, "bytebeam", "journal")?)
+    ///     .firmware_health_check(|_client| {
+    ///         // check that sensors respond, ...
+    ///         true
+    ///     })
+    ///     .connect()?;
+    /// bytebeam_client.enable_ota();
+    /// # anyhow::Ok(())
+    /// ```
+    pub fn firmware_health_check(
+        mut self,
+        health_check: impl Fn(&ByteBeamClient<EspMqttTransport>) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.firmware_health_check = Some(Arc::new(health_check));
+        self
+    }
+
+    /// Connect with Bytebeam cloud
+    ///
+    /// Reads `spiffs/device_config.json` if neither device config nor config source was set
+    pub fn connect(mut self) -> Result<Arc<ByteBeamClient<EspMqttTransport>>> {
+        let device_config = self.load_device_config(config::Spiffs::default())?;
+        let options = self.options;
+
+        let ca_cert = Box::leak(
+            device_config
+                .authentication
+                .ca_certificate
+                .into_boxed_c_str(),
+        );
+        let device_cert = Box::leak(
+            device_config
+                .authentication
+                .device_certificate
+                .into_boxed_c_str(),
+        );
+        let device_key = Box::leak(
+            device_config
+                .authentication
+                .device_private_key
+                .into_boxed_c_str(),
+        );
+
+        let mqtt_config = MqttClientConfiguration {
+            client_id: Some(
+                options
+                    .client_id
+                    .as_deref()
+                    .unwrap_or(&device_config.device_id),
+            ),
+            keep_alive_interval: options.keep_alive,
+            // client reconnects with its own backoff
+            reconnect_timeout: None,
+            buffer_size: options.buffer_size.unwrap_or_default(),
+            out_buffer_size: options.out_buffer_size.unwrap_or_default(),
+            task_stack: options.mqtt_task.stack_size.unwrap_or_default(),
+            task_prio: options.mqtt_task.priority.unwrap_or_default(),
+            server_certificate: Some(X509::pem(ca_cert)),
+            client_certificate: Some(X509::pem(device_cert)),
+            private_key: Some(X509::pem(device_key)),
+            ..Default::default()
+        };
+
+        let broker_uri = format!("mqtts://{}:{}", device_config.broker, device_config.port);
+
+        let (transport, events) =
+            EspMqttTransport::connect(&broker_uri, &mqtt_config, ca_cert, device_cert, device_key)?;
+
+        let pending_verify = rollback::pending_verify();
+        self.extensions.firmware_pending_verify = pending_verify;
+        if pending_verify && self.extensions.journal_store.is_none() {
+            warn!(
+                "No journal store to report outcome of firmware update with, see `journal_store`"
+            );
+        }
+
+        let bytebeam_client = ByteBeamClient::start(
+            device_config.device_id,
+            device_config.project_id,
+            options,
+            self.extensions,
+            transport,
+            events,
+        )?;
+        if pending_verify {
+            rollback::verify_firmware(bytebeam_client.clone(), self.firmware_health_check)?;
+        }
+
+        Ok(bytebeam_client)
+    }
+}
+
+/// Run `f` with threads spawned from current thread getting given `priority`
+pub(crate) fn with_thread_priority<R>(priority: Option<u8>, f: impl FnOnce() -> R) -> R {
+    let Some(priority) = priority else {
+        return f();
+    };
+
+    let conf = ThreadSpawnConfiguration {
+        priority,
+        ..Default::default()
+    };
+    if let Err(e) = conf.set() {
+        warn!("Failed to set thread priority: {e}");
+    }
+    let result = f();
+    ThreadSpawnConfiguration::default().set().ok();
+
+    result
+}
@@ -0,0 +1,249 @@
+use std::{
+    ffi::{CStr, CString},
+    ptr, thread,
+    time::Duration,
+};
+
+use esp_idf_sys::{
+    esp, esp_err_t, esp_http_client_cleanup, esp_http_client_config_t,
+    esp_http_client_fetch_headers, esp_http_client_flush_response, esp_http_client_get_status_code,
+    esp_http_client_handle_t, esp_http_client_init, esp_http_client_open,
+    esp_http_client_read_response, esp_http_client_set_header, esp_http_client_set_redirection,
+    esp_ota_get_next_update_partition, esp_ota_set_boot_partition, esp_partition_erase_range,
+    esp_partition_read, esp_partition_t, esp_partition_write,
+};
+use log::{error, info};
+
+use super::{rollback, EspMqttTransport};
+use crate::{
+    error::Result,
+    firmware::{FirmwareSink, FirmwareSource, FirmwareUpdate, Installed, OtaOptions},
+    ActionContext, ByteBeamError,
+};
+
+/// Size of a flash sector, which is the unit of erasing
+const SECTOR_SIZE: u64 = 4096;
+/// Redirects followed before a download is given up
+const MAX_REDIRECTS: usize = 5;
+
+pub(crate) fn handle_ota(
+    update: FirmwareUpdate,
+    ctx: ActionContext<EspMqttTransport>,
+    options: &OtaOptions,
+) {
+    let mut source = HttpSource::new(&update.url, &ctx.client().transport.lock().unwrap());
+    let result = PartitionSink::next_update()
+        .and_then(|mut sink| update.install(options, &mut source, &mut sink, &ctx));
+
+    match result {
+        Ok(Installed::AlreadyRunning) => {}
+        Ok(Installed::PendingReset) => {
+            info!("Restarting in 1 secs...");
+            thread::sleep(Duration::from_secs(1));
+            ctx.client().restart_device(ctx.id());
+        }
+        // reported as cancelled once `ctx` is dropped
+        Err(e) if ctx.is_cancelled() => info!("OTA stopped: {e}"),
+        Err(e) => {
+            error!("{e}");
+            ctx.fail(&[&e.to_string()]).ok();
+        }
+    }
+}
+
+/// Image downloaded over HTTPS, with certificates of device
+///
+/// Server is trusted if broker would be. `EspHttpConnection` can only take a CA from the global
+/// store of esp-tls, which every other TLS client of firmware shares, so HTTP client of ESP-IDF
+/// is used directly instead.
+struct HttpSource {
+    url: String,
+    ca_cert: &'static CStr,
+    device_cert: &'static CStr,
+    device_key: &'static CStr,
+    connection: Option<HttpConnection>,
+}
+
+impl HttpSource {
+    fn new(url: &str, transport: &EspMqttTransport) -> Self {
+        HttpSource {
+            url: url.into(),
+            ca_cert: transport.ca_cert,
+            device_cert: transport.device_cert,
+            device_key: transport.device_key,
+            connection: None,
+        }
+    }
+}
+
+impl FirmwareSource for HttpSource {
+    fn open(&mut self, offset: u64) -> Result<u64> {
+        // previous connection is closed first, so that both aren't holding buffers at once
+        self.connection = None;
+
+        info!("Opening http client");
+        let url = CString::new(self.url.as_str()).map_err(ByteBeamError::ota)?;
+        let config = esp_http_client_config_t {
+            url: url.as_ptr(),
+            cert_pem: self.ca_cert.as_ptr(),
+            client_cert_pem: self.device_cert.as_ptr(),
+            client_key_pem: self.device_key.as_ptr(),
+            ..Default::default()
+        };
+        let mut connection = HttpConnection::new(&config)?;
+        if offset > 0 {
+            connection.set_header("Range", &format!("bytes={offset}-"))?;
+        }
+        connection.get()?;
+
+        let start = match connection.status() {
+            206 => offset,
+            // server ignored range and sends whole image
+            200 => 0,
+            status => {
+                return Err(ByteBeamError::ota(format!(
+                    "download failed with status {status}"
+                )))
+            }
+        };
+        self.connection = Some(connection);
+
+        Ok(start)
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let connection = self
+            .connection
+            .as_mut()
+            .ok_or_else(|| ByteBeamError::ota("connection isn't open"))?;
+        connection.read(buf)
+    }
+}
+
+/// Request made with HTTP client of ESP-IDF
+struct HttpConnection(esp_http_client_handle_t);
+
+impl HttpConnection {
+    fn new(config: &esp_http_client_config_t) -> Result<Self> {
+        let client = unsafe { esp_http_client_init(config) };
+        if client.is_null() {
+            return Err(ByteBeamError::ota("failed to create http client"));
+        }
+
+        Ok(HttpConnection(client))
+    }
+
+    fn set_header(&mut self, name: &str, value: &str) -> Result<()> {
+        let name = CString::new(name).map_err(ByteBeamError::ota)?;
+        let value = CString::new(value).map_err(ByteBeamError::ota)?;
+        esp!(unsafe { esp_http_client_set_header(self.0, name.as_ptr(), value.as_ptr()) })
+            .map_err(ByteBeamError::ota)
+    }
+
+    /// Send GET request and read headers of response, following redirects
+    fn get(&mut self) -> Result<()> {
+        for _ in 0..=MAX_REDIRECTS {
+            esp!(unsafe { esp_http_client_open(self.0, 0) }).map_err(ByteBeamError::ota)?;
+            let len = unsafe { esp_http_client_fetch_headers(self.0) };
+            if len < 0 {
+                esp!(len as esp_err_t).map_err(ByteBeamError::ota)?;
+            }
+
+            if !matches!(self.status(), 301 | 302 | 303 | 307 | 308) {
+                return Ok(());
+            }
+
+            let mut len = 0;
+            esp!(unsafe { esp_http_client_flush_response(self.0, &mut len) })
+                .map_err(ByteBeamError::ota)?;
+            esp!(unsafe { esp_http_client_set_redirection(self.0) }).map_err(ByteBeamError::ota)?;
+        }
+
+        Err(ByteBeamError::ota("too many redirects"))
+    }
+
+    fn status(&self) -> u16 {
+        unsafe { esp_http_client_get_status_code(self.0) as u16 }
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let read =
+            unsafe { esp_http_client_read_response(self.0, buf.as_mut_ptr() as _, buf.len() as _) };
+        if read < 0 {
+            esp!(read as esp_err_t).map_err(ByteBeamError::ota)?;
+        }
+
+        Ok(read as usize)
+    }
+}
+
+impl Drop for HttpConnection {
+    fn drop(&mut self) {
+        unsafe { esp_http_client_cleanup(self.0) };
+    }
+}
+
+/// Partition which will be booted into after an update
+///
+/// Written directly instead of through `EspOta`, which erases whole partition when an update
+/// begins and can only append to it, so that a download can continue after a reset. Like
+/// `esp_ota_begin`, refuses to overwrite previous firmware while running one is yet to be
+/// verified, as that is the firmware a roll back would boot into.
+struct PartitionSink(*const esp_partition_t);
+
+impl PartitionSink {
+    fn next_update() -> Result<Self> {
+        if rollback::pending_verify() {
+            return Err(ByteBeamError::ota(
+                "running firmware is yet to pass its health check",
+            ));
+        }
+
+        let partition = unsafe { esp_ota_get_next_update_partition(ptr::null()) };
+        if partition.is_null() {
+            return Err(ByteBeamError::ota("no partition to update"));
+        }
+
+        Ok(PartitionSink(partition))
+    }
+}
+
+impl FirmwareSink for PartitionSink {
+    fn erase(&mut self, len: u64) -> Result<()> {
+        if len > unsafe { (*self.0).size } as u64 {
+            return Err(ByteBeamError::ota("image doesn't fit in update partition"));
+        }
+
+        let len = len.next_multiple_of(SECTOR_SIZE);
+        esp!(unsafe { esp_partition_erase_range(self.0, 0, len as usize) })
+            .map_err(ByteBeamError::ota)
+    }
+
+    fn read(&mut self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        esp!(unsafe {
+            esp_partition_read(self.0, offset as usize, buf.as_mut_ptr() as _, buf.len())
+        })
+        .map_err(ByteBeamError::ota)
+    }
+
+    fn write(&mut self, offset: u64, data: &[u8]) -> Result<()> {
+        // encrypted partitions are written in multiples of 16 bytes, only last block can be short
+        let mut padded;
+        let data = if data.len() % 16 == 0 {
+            data
+        } else {
+            padded = data.to_vec();
+            padded.resize(data.len().next_multiple_of(16), 0xff);
+            &padded
+        };
+
+        esp!(unsafe {
+            esp_partition_write(self.0, offset as usize, data.as_ptr() as _, data.len())
+        })
+        .map_err(ByteBeamError::ota)
+    }
+
+    fn activate(&mut self) -> Result<()> {
+        esp!(unsafe { esp_ota_set_boot_partition(self.0) }).map_err(ByteBeamError::ota)
+    }
+}
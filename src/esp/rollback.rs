@@ -0,0 +1,79 @@
+use std::{
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use esp_idf_svc::ota::EspOta;
+use esp_idf_sys::{
+    esp_ota_get_running_partition, esp_ota_get_state_partition, esp_ota_img_states_t,
+    esp_ota_img_states_t_ESP_OTA_IMG_PENDING_VERIFY, ESP_OK,
+};
+use log::{error, info};
+
+use super::EspMqttTransport;
+use crate::{error::Result, ByteBeamClient, ConnectionState};
+
+/// Check of firmware booted after an OTA update, see
+/// [`ByteBeamClientBuilder::firmware_health_check`](crate::ByteBeamClientBuilder::firmware_health_check)
+pub(crate) type HealthCheck = Arc<dyn Fn(&ByteBeamClient<EspMqttTransport>) -> bool + Send + Sync>;
+
+/// Whether running firmware was just installed and waits to be marked valid
+pub(crate) fn pending_verify() -> bool {
+    let mut state: esp_ota_img_states_t = 0;
+    let ret = unsafe { esp_ota_get_state_partition(esp_ota_get_running_partition(), &mut state) };
+    ret == ESP_OK && state == esp_ota_img_states_t_ESP_OTA_IMG_PENDING_VERIFY
+}
+
+/// Mark running firmware valid if it connects with broker and passes `health_check` in time,
+/// roll back to previous one otherwise
+pub(crate) fn verify_firmware(
+    bytebeam_client: Arc<ByteBeamClient<EspMqttTransport>>,
+    health_check: Option<HealthCheck>,
+) -> Result<()> {
+    let timeout = bytebeam_client.options.firmware_check_timeout;
+    let cloned_client = bytebeam_client.clone();
+    bytebeam_client
+        .options
+        .event_thread
+        .spawn("bytebeam-firmware-check", move || {
+            let bytebeam_client = cloned_client;
+            let healthy = wait_for_connection(&bytebeam_client, timeout)
+                && health_check.map_or(true, |check| check(&bytebeam_client));
+
+            if healthy {
+                info!("New firmware is healthy, marking it valid");
+                if let Err(e) = EspOta::new().and_then(|mut ota| ota.mark_running_slot_valid()) {
+                    error!("Failed to mark firmware valid: {e}");
+                }
+                bytebeam_client.release_reset_completions(true);
+            } else {
+                error!("New firmware failed health check, rolling back");
+                bytebeam_client.release_reset_completions(false);
+                // give MQTT client a chance to send status
+                thread::sleep(Duration::from_secs(1));
+                let e = match EspOta::new() {
+                    Ok(mut ota) => ota.mark_running_slot_invalid_and_reboot(),
+                    Err(e) => e,
+                };
+                error!("Failed to roll back firmware: {e}");
+            }
+        })?;
+
+    Ok(())
+}
+
+fn wait_for_connection(
+    bytebeam_client: &ByteBeamClient<EspMqttTransport>,
+    timeout: Duration,
+) -> bool {
+    let started_at = Instant::now();
+    while started_at.elapsed() < timeout {
+        if bytebeam_client.connection_state() == ConnectionState::Connected {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    false
+}
@@ -0,0 +1,65 @@
+use embedded_svc::storage::RawStorage;
+use esp_idf_svc::nvs::{EspNvs, EspNvsPartition, NvsPartitionId};
+
+use crate::{error::Result, store::BlobStore, ByteBeamError};
+
+/// Longest key NVS allows
+const MAX_KEY_LEN: usize = 15;
+
+/// Blob stored under a key of a NVS namespace
+///
+/// Several stores can share a namespace, as long as each uses a key of its own.
+///
+/// # Example
+/// ```no_run
+/// use bytebeam_esp_rs::{store, ByteBeamClient};
+/// use esp_idf_svc::nvs::EspDefaultNvsPartition;
+///
+/// let nvs = EspDefaultNvsPartition::take()?;
+/// let bytebeam_client = ByteBeamClient::builder()
+///     .journal_store(store::Nvs::new(nvs.clone(), "bytebeam", "journal")?)
+///     .download_store(store::Nvs::new(nvs, "bytebeam", "download")?)
+///     .connect()?;
+/// # anyhow::Ok(())
+/// ```
+pub struct Nvs<T: NvsPartitionId> {
+    nvs: EspNvs<T>,
+    key: String,
+}
+
+impl<T: NvsPartitionId> Nvs<T> {
+    /// Blob stored under `key` in `namespace` of `partition`
+    pub fn new(partition: EspNvsPartition<T>, namespace: &str, key: &str) -> Result<Self> {
+        if key.len() > MAX_KEY_LEN {
+            return Err(ByteBeamError::storage(format!(
+                "key {key} is longer than {MAX_KEY_LEN} characters"
+            )));
+        }
+
+        let nvs = EspNvs::new(partition, namespace, true).map_err(ByteBeamError::storage)?;
+        Ok(Nvs {
+            nvs,
+            key: key.into(),
+        })
+    }
+}
+
+impl<T: NvsPartitionId> BlobStore for Nvs<T> {
+    fn load(&mut self) -> Result<Option<Vec<u8>>> {
+        let Some(len) = RawStorage::len(&self.nvs, &self.key).map_err(ByteBeamError::storage)?
+        else {
+            return Ok(None);
+        };
+
+        let mut blob = vec![0; len];
+        let blob = RawStorage::get_raw(&self.nvs, &self.key, &mut blob)
+            .map_err(ByteBeamError::storage)?
+            .map(<[u8]>::to_vec);
+        Ok(blob)
+    }
+
+    fn store(&mut self, blob: &[u8]) -> Result<()> {
+        RawStorage::set_raw(&mut self.nvs, &self.key, blob).map_err(ByteBeamError::storage)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,194 @@
+use std::{
+    ffi::{CStr, CString},
+    ptr,
+    time::Duration,
+};
+
+use esp_idf_svc::ota::EspOta;
+use esp_idf_sys::{
+    eTaskState_eBlocked, eTaskState_eDeleted, eTaskState_eReady, eTaskState_eRunning,
+    eTaskState_eSuspended, esp, esp_get_free_heap_size, esp_get_minimum_free_heap_size,
+    esp_log_level_set, esp_log_level_t_ESP_LOG_DEBUG, esp_log_level_t_ESP_LOG_ERROR,
+    esp_log_level_t_ESP_LOG_INFO, esp_log_level_t_ESP_LOG_NONE, esp_log_level_t_ESP_LOG_VERBOSE,
+    esp_log_level_t_ESP_LOG_WARN, esp_ota_get_app_description, esp_restart, esp_task_wdt_add,
+    esp_task_wdt_init, esp_timer_get_time, esp_wifi_sta_get_ap_info, nvs_entry_find,
+    nvs_entry_info, nvs_entry_info_t, nvs_entry_next, nvs_get_stats, nvs_stats_t, nvs_type_t,
+    nvs_type_t_NVS_TYPE_ANY, nvs_type_t_NVS_TYPE_BLOB, nvs_type_t_NVS_TYPE_I16,
+    nvs_type_t_NVS_TYPE_I32, nvs_type_t_NVS_TYPE_I64, nvs_type_t_NVS_TYPE_I8,
+    nvs_type_t_NVS_TYPE_STR, nvs_type_t_NVS_TYPE_U16, nvs_type_t_NVS_TYPE_U32,
+    nvs_type_t_NVS_TYPE_U64, nvs_type_t_NVS_TYPE_U8, uxTaskGetNumberOfTasks, uxTaskGetSystemState,
+    wifi_ap_record_t, TaskHandle_t, TaskStatus_t,
+};
+use log::{error, LevelFilter};
+
+use crate::{error::Result, ByteBeamError};
+
+/// Label of running partition and version of firmware in it
+pub(crate) fn running_firmware() -> (Option<String>, Option<String>) {
+    // fails if application holds on to its own `EspOta`
+    match EspOta::new().and_then(|ota| ota.get_running_slot()) {
+        Ok(slot) => (
+            Some(slot.label.as_str().into()),
+            slot.firmware
+                .map(|firmware| firmware.version.as_str().into()),
+        ),
+        Err(_) => (None, None),
+    }
+}
+
+/// Version in app descriptor of running firmware
+pub(crate) fn app_version() -> Option<String> {
+    let version = unsafe { CStr::from_ptr((*esp_ota_get_app_description()).version.as_ptr()) };
+    version.to_str().ok().map(Into::into)
+}
+
+pub(crate) fn free_heap() -> u32 {
+    unsafe { esp_get_free_heap_size() }
+}
+
+pub(crate) fn uptime() -> Duration {
+    Duration::from_micros(unsafe { esp_timer_get_time() } as u64)
+}
+
+pub(crate) fn min_free_heap() -> u32 {
+    unsafe { esp_get_minimum_free_heap_size() }
+}
+
+/// A FreeRTOS task, as listed by [`tasks`]
+pub(crate) struct TaskInfo {
+    pub name: String,
+    pub state: &'static str,
+    pub priority: u32,
+    /// Least amount of free stack task ever had, in bytes
+    pub min_free_stack: u32,
+}
+
+/// All FreeRTOS tasks, needs `CONFIG_FREERTOS_USE_TRACE_FACILITY`
+pub(crate) fn tasks() -> Vec<TaskInfo> {
+    // some room for tasks created between counting and listing them
+    let capacity = unsafe { uxTaskGetNumberOfTasks() } as usize + 4;
+    let mut statuses: Vec<TaskStatus_t> = Vec::with_capacity(capacity);
+    unsafe {
+        let len = uxTaskGetSystemState(statuses.as_mut_ptr(), capacity as _, ptr::null_mut());
+        statuses.set_len(len as usize);
+    }
+
+    statuses
+        .iter()
+        .map(|status| TaskInfo {
+            name: unsafe { CStr::from_ptr(status.pcTaskName) }
+                .to_string_lossy()
+                .into_owned(),
+            state: match status.eCurrentState {
+                eTaskState_eRunning => "running",
+                eTaskState_eReady => "ready",
+                eTaskState_eBlocked => "blocked",
+                eTaskState_eSuspended => "suspended",
+                eTaskState_eDeleted => "deleted",
+                _ => "invalid",
+            },
+            priority: status.uxCurrentPriority as u32,
+            min_free_stack: status.usStackHighWaterMark as u32,
+        })
+        .collect()
+}
+
+/// Signal strength of access point station is connected to, in dBm
+pub(crate) fn wifi_rssi() -> Result<i8> {
+    let mut ap_info = wifi_ap_record_t::default();
+    esp!(unsafe { esp_wifi_sta_get_ap_info(&mut ap_info) })
+        .map_err(|e| ByteBeamError::Other(e.into()))?;
+    Ok(ap_info.rssi)
+}
+
+/// Seconds task watchdog waits for a hung task before resetting device
+const WATCHDOG_TIMEOUT_S: u32 = 5;
+
+/// Have task watchdog reset device because `task` is hung
+///
+/// `task` is subscribed to watchdog, which it never feeds, so that watchdog panics with a
+/// backtrace of it. Device is restarted right away if that isn't possible.
+pub(crate) fn watchdog_reset(task: usize) {
+    // also reconfigures a running watchdog, which might only log instead of panicking
+    let subscribed = esp!(unsafe { esp_task_wdt_init(WATCHDOG_TIMEOUT_S, true) })
+        .and_then(|()| esp!(unsafe { esp_task_wdt_add(task as TaskHandle_t) }));
+    if let Err(e) = subscribed {
+        error!("Failed to hand hung task to watchdog: {e}");
+        unsafe { esp_restart() };
+    }
+}
+
+/// Usage of entries in default NVS partition
+pub(crate) fn nvs_stats() -> Result<nvs_stats_t> {
+    let mut stats = nvs_stats_t::default();
+    esp!(unsafe { nvs_get_stats(ptr::null(), &mut stats) }).map_err(ByteBeamError::storage)?;
+    Ok(stats)
+}
+
+/// A key in NVS, as listed by [`nvs_entries`]
+pub(crate) struct NvsEntry {
+    pub namespace: String,
+    pub key: String,
+    pub kind: &'static str,
+}
+
+/// Keys in default NVS partition, of `namespace` or of all namespaces if it is `None`
+pub(crate) fn nvs_entries(namespace: Option<&str>) -> Result<Vec<NvsEntry>> {
+    let namespace = namespace
+        .map(CString::new)
+        .transpose()
+        .map_err(ByteBeamError::storage)?;
+    let namespace = namespace
+        .as_ref()
+        .map_or(ptr::null(), |namespace| namespace.as_ptr());
+
+    let mut entries = Vec::new();
+    // `NULL` once there are no more entries, iterator is released by then
+    let mut iterator =
+        unsafe { nvs_entry_find(b"nvs\0".as_ptr() as _, namespace, nvs_type_t_NVS_TYPE_ANY) };
+    while !iterator.is_null() {
+        let mut info = nvs_entry_info_t::default();
+        unsafe { nvs_entry_info(iterator, &mut info) };
+        entries.push(NvsEntry {
+            namespace: unsafe { CStr::from_ptr(info.namespace_name.as_ptr()) }
+                .to_string_lossy()
+                .into_owned(),
+            key: unsafe { CStr::from_ptr(info.key.as_ptr()) }
+                .to_string_lossy()
+                .into_owned(),
+            kind: nvs_type_name(info.type_),
+        });
+        iterator = unsafe { nvs_entry_next(iterator) };
+    }
+
+    Ok(entries)
+}
+
+fn nvs_type_name(kind: nvs_type_t) -> &'static str {
+    match kind {
+        nvs_type_t_NVS_TYPE_U8 => "u8",
+        nvs_type_t_NVS_TYPE_I8 => "i8",
+        nvs_type_t_NVS_TYPE_U16 => "u16",
+        nvs_type_t_NVS_TYPE_I16 => "i16",
+        nvs_type_t_NVS_TYPE_U32 => "u32",
+        nvs_type_t_NVS_TYPE_I32 => "i32",
+        nvs_type_t_NVS_TYPE_U64 => "u64",
+        nvs_type_t_NVS_TYPE_I64 => "i64",
+        nvs_type_t_NVS_TYPE_STR => "string",
+        nvs_type_t_NVS_TYPE_BLOB => "blob",
+        _ => "unknown",
+    }
+}
+
+/// Set level of ESP-IDF logs of all components
+pub(crate) fn set_log_level(level: LevelFilter) {
+    let level = match level {
+        LevelFilter::Off => esp_log_level_t_ESP_LOG_NONE,
+        LevelFilter::Error => esp_log_level_t_ESP_LOG_ERROR,
+        LevelFilter::Warn => esp_log_level_t_ESP_LOG_WARN,
+        LevelFilter::Info => esp_log_level_t_ESP_LOG_INFO,
+        LevelFilter::Debug => esp_log_level_t_ESP_LOG_DEBUG,
+        LevelFilter::Trace => esp_log_level_t_ESP_LOG_VERBOSE,
+    };
+    unsafe { esp_log_level_set(b"*\0".as_ptr() as _, level) }
+}
@@ -0,0 +1,216 @@
+use std::{
+    ffi::{c_void, CStr},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Mutex,
+    },
+};
+
+use embedded_svc::mqtt::client::{Details, Event};
+use esp_idf_svc::{
+    handle::RawHandle,
+    mqtt::client::{EspMqttClient, MqttClientConfiguration},
+};
+use esp_idf_sys::{
+    esp, esp_event_base_t, esp_mqtt_client_reconnect, esp_mqtt_client_register_event,
+    esp_mqtt_connect_return_code_t_MQTT_CONNECTION_REFUSE_BAD_USERNAME,
+    esp_mqtt_connect_return_code_t_MQTT_CONNECTION_REFUSE_ID_REJECTED,
+    esp_mqtt_connect_return_code_t_MQTT_CONNECTION_REFUSE_NOT_AUTHORIZED,
+    esp_mqtt_connect_return_code_t_MQTT_CONNECTION_REFUSE_PROTOCOL,
+    esp_mqtt_connect_return_code_t_MQTT_CONNECTION_REFUSE_SERVER_UNAVAILABLE,
+    esp_mqtt_error_codes_t, esp_mqtt_error_type_t_MQTT_ERROR_TYPE_CONNECTION_REFUSED,
+    esp_mqtt_error_type_t_MQTT_ERROR_TYPE_TCP_TRANSPORT, esp_mqtt_event_id_t_MQTT_EVENT_ERROR,
+    esp_mqtt_event_t, EspError,
+};
+use log::info;
+
+use crate::{
+    error::Result,
+    transport::{QoS, Transport, TransportEvent},
+    ByteBeamError,
+};
+
+/// Returned by `esp_mqtt_client_publish` when outbox is full
+const OUTBOX_FULL: i32 = -2;
+
+/// Transport backed by ESP-IDF's MQTT client
+pub struct EspMqttTransport {
+    client: EspMqttClient,
+    pub(crate) ca_cert: &'static CStr,
+    pub(crate) device_cert: &'static CStr,
+    pub(crate) device_key: &'static CStr,
+    /// Dropped after `client`, which stops calling it
+    _error_handler: Box<ErrorHandler>,
+}
+
+impl EspMqttTransport {
+    /// Connect to `broker_uri`
+    ///
+    /// Certificates in `mqtt_config` are also used for downloading firmware during OTA
+    pub fn connect(
+        broker_uri: &str,
+        mqtt_config: &MqttClientConfiguration,
+        ca_cert: &'static CStr,
+        device_cert: &'static CStr,
+        device_key: &'static CStr,
+    ) -> Result<(Self, Receiver<TransportEvent>)> {
+        let (tx, rx) = mpsc::channel();
+        let error_handler = Box::new(ErrorHandler(Mutex::new(tx.clone())));
+
+        let client = EspMqttClient::new(broker_uri, mqtt_config, move |message_event| {
+            let event = match message_event {
+                Ok(Event::Connected(_)) => TransportEvent::Connected,
+                Ok(Event::Disconnected) => TransportEvent::Disconnected,
+                Ok(Event::Received(data)) if data.details() == &Details::Complete => {
+                    TransportEvent::Received {
+                        topic: data.topic().unwrap_or_default().into(),
+                        payload: data.data().to_vec(),
+                    }
+                }
+                // reported with details by `on_error`
+                Err(_) => return,
+                _ => {
+                    info!("EVENT: {message_event:?}");
+                    return;
+                }
+            };
+            tx.send(event).ok();
+        })
+        .map_err(ByteBeamError::transport)?;
+
+        // `EspMqttClient` reports every error as `ESP_FAIL`, details are read from raw event
+        esp!(unsafe {
+            esp_mqtt_client_register_event(
+                client.handle(),
+                esp_mqtt_event_id_t_MQTT_EVENT_ERROR,
+                Some(on_error),
+                &*error_handler as *const ErrorHandler as *mut c_void,
+            )
+        })
+        .map_err(ByteBeamError::transport)?;
+
+        let transport = EspMqttTransport {
+            client,
+            ca_cert,
+            device_cert,
+            device_key,
+            _error_handler: error_handler,
+        };
+
+        Ok((transport, rx))
+    }
+}
+
+impl Transport for EspMqttTransport {
+    fn publish(&mut self, topic: &str, qos: QoS, payload: &[u8]) -> Result<u32> {
+        self.client
+            .publish(topic, qos.into(), false, payload)
+            .map_err(publish_error)
+    }
+
+    fn subscribe(&mut self, topic: &str, qos: QoS) -> Result<u32> {
+        self.client
+            .subscribe(topic, qos.into())
+            .map_err(ByteBeamError::transport)
+    }
+
+    fn reconnect(&mut self) -> Result<()> {
+        esp!(unsafe { esp_mqtt_client_reconnect(self.client.handle()) })
+            .map_err(ByteBeamError::transport)
+    }
+}
+
+/// Forwards errors of MQTT client to event stream
+struct ErrorHandler(Mutex<Sender<TransportEvent>>);
+
+unsafe extern "C" fn on_error(
+    handler: *mut c_void,
+    _base: esp_event_base_t,
+    _event_id: i32,
+    event: *mut c_void,
+) {
+    let handler = &*(handler as *const ErrorHandler);
+    let event = &*(event as *const esp_mqtt_event_t);
+    if event.error_handle.is_null() {
+        return;
+    }
+
+    let error = mqtt_error(&*event.error_handle);
+    handler
+        .0
+        .lock()
+        .unwrap()
+        .send(TransportEvent::Error(error))
+        .ok();
+}
+
+#[allow(non_upper_case_globals)]
+fn mqtt_error(codes: &esp_mqtt_error_codes_t) -> ByteBeamError {
+    match codes.error_type {
+        esp_mqtt_error_type_t_MQTT_ERROR_TYPE_CONNECTION_REFUSED => {
+            let reason = match codes.connect_return_code {
+                esp_mqtt_connect_return_code_t_MQTT_CONNECTION_REFUSE_PROTOCOL => {
+                    "unacceptable protocol version".into()
+                }
+                esp_mqtt_connect_return_code_t_MQTT_CONNECTION_REFUSE_ID_REJECTED => {
+                    "client id rejected".into()
+                }
+                esp_mqtt_connect_return_code_t_MQTT_CONNECTION_REFUSE_SERVER_UNAVAILABLE => {
+                    "server unavailable".into()
+                }
+                esp_mqtt_connect_return_code_t_MQTT_CONNECTION_REFUSE_BAD_USERNAME => {
+                    "bad username or password".into()
+                }
+                esp_mqtt_connect_return_code_t_MQTT_CONNECTION_REFUSE_NOT_AUTHORIZED => {
+                    "not authorized".into()
+                }
+                code => format!("return code {code}"),
+            };
+            ByteBeamError::ConnectionRefused(reason.into())
+        }
+        // handshake failed, or certificate of either side was rejected
+        esp_mqtt_error_type_t_MQTT_ERROR_TYPE_TCP_TRANSPORT
+            if codes.esp_tls_stack_err != 0 || codes.esp_tls_cert_verify_flags != 0 =>
+        {
+            ByteBeamError::Tls(
+                format!(
+                    "{}, mbedtls error -{:#x}, certificate verification flags {:#x}",
+                    esp_error(codes.esp_tls_last_esp_err),
+                    codes.esp_tls_stack_err.unsigned_abs(),
+                    codes.esp_tls_cert_verify_flags
+                )
+                .into(),
+            )
+        }
+        esp_mqtt_error_type_t_MQTT_ERROR_TYPE_TCP_TRANSPORT => ByteBeamError::transport(format!(
+            "{}, socket errno {}",
+            esp_error(codes.esp_tls_last_esp_err),
+            codes.esp_transport_sock_errno
+        )),
+        error_type => ByteBeamError::transport(format!("MQTT error type {error_type}")),
+    }
+}
+
+fn esp_error(code: i32) -> String {
+    match EspError::from(code) {
+        Some(e) => e.to_string(),
+        None => "no ESP-IDF error".into(),
+    }
+}
+
+fn publish_error(e: EspError) -> ByteBeamError {
+    match e.code() {
+        OUTBOX_FULL => ByteBeamError::QueueFull,
+        _ => ByteBeamError::transport(e),
+    }
+}
+
+impl From<QoS> for embedded_svc::mqtt::client::QoS {
+    fn from(qos: QoS) -> Self {
+        match qos {
+            QoS::AtMostOnce => Self::AtMostOnce,
+            QoS::AtLeastOnce => Self::AtLeastOnce,
+            QoS::ExactlyOnce => Self::ExactlyOnce,
+        }
+    }
+}
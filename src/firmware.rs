@@ -0,0 +1,1159 @@
+//! Parts of firmware updates which don't depend on platform
+//!
+//! Images downloaded by OTA updates are checked by an [`ImageVerifier`] before device boots into
+//! them: size is checked against `content-length` of update, and SHA-256 digest against
+//! `checksum`, if update has one. With `signature` feature, updates can also be required to carry
+//! an Ed25519 signature of the SHA-256 digest, made with a key whose public half is built into
+//! firmware.
+//!
+//! Update is downloaded by [`FirmwareUpdate::install`] from a [`FirmwareSource`] into a
+//! [`FirmwareSink`], which on ESP-IDF are the HTTP server in update and the OTA partition.
+//! [`MemorySource`] and [`MemorySink`] stand in for them on host.
+//!
+//! Version of update is compared with the running one as semantic versions, by default updates
+//! to the running version complete right away and older versions are rejected, see
+//! [`VersionPolicy`]. Running version is also published to [`DEVICE_SHADOW`] stream every time
+//! client connects.
+//!
+//! Downloads which fail midway are retried from where they stopped, using HTTP range requests.
+//! With a [`download_store`](crate::ByteBeamClientBuilder::download_store), progress of download
+//! also survives a reset, so that an update of same image continues from where the previous
+//! attempt stopped.
+//!
+//! # Example
+//! ```no_run
+//! use bytebeam_esp_rs::{store, ByteBeamClient};
+//!
+//! let bytebeam_client = ByteBeamClient::builder()
+//!     .download_store(store::File::new("/littlefs/download.json"))
+//!     .connect()?;
+//! # anyhow::Ok(())
+//! ```
+use std::{
+    cmp::Ordering,
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    error::Result, store::BlobStore, transport::Transport, ActionContext, ByteBeamClient,
+    ByteBeamError,
+};
+
+/// Stream to which version of running firmware is published on connect
+pub const DEVICE_SHADOW: &str = "device_shadow";
+
+/// Image is written to sink in blocks of this size, which is the size of a flash sector
+const BLOCK_SIZE: usize = 4096;
+/// Download progress is stored after every this many bytes, to spare flash holding the store
+const CHECKPOINT_INTERVAL: u64 = 64 * 1024;
+/// Interval at which cancellation is checked while waiting to retry a download
+const CANCEL_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Checks and retries applied to firmware updates, see [`ByteBeamClient::enable_ota_with`](crate::ByteBeamClient::enable_ota_with)
+#[derive(Clone, Debug)]
+pub struct OtaOptions {
+    /// Fail updates without a SHA-256 `checksum` of image
+    pub require_checksum: bool,
+    /// Ed25519 key with which images must be signed, updates without a valid `signature` fail
+    ///
+    /// Requires `signature` feature.
+    #[cfg(feature = "signature")]
+    pub public_key: Option<[u8; 32]>,
+    /// Times an interrupted download is resumed before update fails, budget is refilled whenever
+    /// a retry makes progress
+    pub retries: u32,
+    /// Wait between retries of a download
+    pub retry_delay: Duration,
+    /// Which versions are installed, compared with the running one
+    pub version_policy: VersionPolicy,
+}
+
+/// Updates to install depending on their version, updates are always installed if running
+/// version isn't known
+///
+/// Versions are compared as semantic versions, with an optional `v` prefix. Updates whose
+/// version can't be compared that way are installed unless version is the same.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum VersionPolicy {
+    /// Install newer versions only: same version completes without reflashing, an older one fails
+    #[default]
+    UpgradeOnly,
+    /// Install any other version, same version completes without reflashing
+    SkipSame,
+    /// Install every update, even of the running version
+    Force,
+}
+
+/// What [`FirmwareUpdate::install`] did
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Installed {
+    /// Image was written, device boots into it on next reset
+    PendingReset,
+    /// Version of update is already running, action was completed without downloading it
+    AlreadyRunning,
+}
+
+impl Default for OtaOptions {
+    fn default() -> Self {
+        OtaOptions {
+            require_checksum: false,
+            #[cfg(feature = "signature")]
+            public_key: None,
+            retries: 5,
+            retry_delay: Duration::from_secs(10),
+            version_policy: VersionPolicy::UpgradeOnly,
+        }
+    }
+}
+
+/// Firmware update sent by cloud as payload of "update_firmware" action
+#[derive(Clone, Debug, Deserialize)]
+pub struct FirmwareUpdate {
+    /// Where image can be downloaded from
+    pub url: String,
+    pub version: String,
+    #[serde(rename = "content-length")]
+    pub content_length: u64,
+    /// SHA-256 digest of image, in hex
+    #[serde(default)]
+    pub checksum: Option<String>,
+    /// Ed25519 signature of SHA-256 digest of image, in hex
+    #[serde(default)]
+    pub signature: Option<String>,
+}
+
+impl FirmwareUpdate {
+    /// Download image from `source` into `sink`, and have device boot into it once verified
+    ///
+    /// Progress is reported through `ctx`, and action is marked to be completed by the reset
+    /// into new firmware, which is up to caller. Nothing is downloaded if version of update is
+    /// already running, see [`VersionPolicy`]. Download stops with an error once action is
+    /// cancelled, keeping progress for the next update of same image.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use bytebeam_esp_rs::{
+    ///     firmware::{FirmwareUpdate, MemorySink, MemorySource, OtaOptions},
+    ///     transport::MockTransport,
+    ///     ByteBeamClient, DeviceConfig,
+    /// };
+    ///
+    /// # let device_config = DeviceConfig::from_slice(br#"{
+    /// #     "project_id": "demo",
+    /// #     "broker": "localhost",
+    /// #     "port": 1883,
+    /// #     "device_id": "1",
+    /// #     "authentication": {
+    /// #         "ca_certificate": "",
+    /// #         "device_certificate": "",
+    /// #         "device_private_key": ""
+    /// #     }
+    /// # }"#)?;
+    /// let (transport, events, broker) = MockTransport::new();
+    /// let client = ByteBeamClient::builder()
+    ///     .device_config(device_config)
+    ///     .connect_with(transport, events)?;
+    /// client.register_typed_action("update_firmware".into(), |update: FirmwareUpdate, ctx| {
+    ///     let mut source = MemorySource::new(b"new firmware".to_vec());
+    ///     let mut sink = MemorySink::new();
+    ///     match update.install(&OtaOptions::default(), &mut source, &mut sink, &ctx) {
+    ///         // reported as cancelled once `ctx` is dropped
+    ///         Err(_) if ctx.is_cancelled() => {}
+    ///         Err(e) => {
+    ///             ctx.fail(&[&e.to_string()]).ok();
+    ///         }
+    ///         Ok(_) => {}
+    ///     }
+    /// });
+    ///
+    /// // ... send "update_firmware" action through `broker`
+    /// # anyhow::Ok(())
+    /// ```
+    pub fn install<T: Transport>(
+        &self,
+        options: &OtaOptions,
+        source: &mut dyn FirmwareSource,
+        sink: &mut dyn FirmwareSink,
+        ctx: &ActionContext<T>,
+    ) -> Result<Installed> {
+        if let Some(running) = ctx.client().firmware_version() {
+            if self.is_running(running, options.version_policy)? {
+                info!("firmware version {running} is already running");
+                ctx.complete()?;
+                return Ok(Installed::AlreadyRunning);
+            }
+        }
+
+        // checked before anything is downloaded, so that a bad payload doesn't cost a download
+        let verifier = ImageVerifier::for_update(
+            options,
+            Some(self.content_length),
+            self.checksum.as_deref(),
+            self.signature.as_deref(),
+        )?;
+
+        let mut store = ctx
+            .client()
+            .download_store
+            .as_ref()
+            .map(|store| store.lock().unwrap());
+        let mut downloader = Downloader {
+            download: Download {
+                version: self.version.clone(),
+                content_length: self.content_length,
+                checksum: self.checksum.clone(),
+                offset: 0,
+            },
+            verifier,
+            sink,
+            store: store.as_deref_mut().map(|store| store.as_mut()),
+            block: vec![0xff; BLOCK_SIZE],
+            filled: 0,
+        };
+
+        info!("upgrading firmware version to {}", self.version);
+        downloader.resume_or_erase()?;
+
+        let mut retries = options.retries;
+        loop {
+            let offset = downloader.download.offset;
+            match downloader.fetch(source, ctx) {
+                Ok(()) => break,
+                Err(Failure::Fatal(e)) => {
+                    downloader.clear();
+                    return Err(e);
+                }
+                Err(Failure::Interrupted(e)) => {
+                    // progress is kept, so that next update of same image continues from here
+                    downloader.save();
+                    if downloader.download.offset > offset {
+                        retries = options.retries;
+                    }
+                    if retries == 0 || ctx.is_cancelled() {
+                        return Err(e);
+                    }
+                    retries -= 1;
+                    warn!(
+                        "download interrupted at {} bytes: {e}, retrying in {:?}",
+                        downloader.download.offset, options.retry_delay
+                    );
+                    if !sleep_unless_cancelled(ctx, options.retry_delay) {
+                        return Err(ByteBeamError::ota("download cancelled"));
+                    }
+                }
+            }
+        }
+
+        // image is either booted into or thrown away from here on
+        downloader.clear();
+        info!("verifying image");
+        downloader.verifier.finish()?;
+        info!("changing boot partition");
+        downloader.sink.activate()?;
+
+        // reported as completed once device boots with new firmware
+        ctx.complete_after_reset();
+        Ok(Installed::PendingReset)
+    }
+
+    /// Whether update is of `running` version and can be skipped, fails for downgrades
+    /// which `policy` doesn't allow
+    fn is_running(&self, running: &str, policy: VersionPolicy) -> Result<bool> {
+        if policy == VersionPolicy::Force {
+            return Ok(false);
+        }
+
+        match compare_versions(&self.version, running) {
+            Some(Ordering::Equal) => Ok(true),
+            Some(Ordering::Less) if policy == VersionPolicy::UpgradeOnly => {
+                Err(ByteBeamError::ota(format!(
+                    "version {} is older than running version {running}",
+                    self.version
+                )))
+            }
+            Some(_) => Ok(false),
+            None => Ok(self.version == running),
+        }
+    }
+}
+
+/// Sleep for `delay`, returning `false` early if action gets cancelled meanwhile
+fn sleep_unless_cancelled<T: Transport>(ctx: &ActionContext<T>, delay: Duration) -> bool {
+    let deadline = Instant::now() + delay;
+    loop {
+        if ctx.is_cancelled() {
+            return false;
+        }
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return true;
+        }
+        thread::sleep(remaining.min(CANCEL_CHECK_INTERVAL));
+    }
+}
+
+/// Order of `version` relative to `other`, `None` if either isn't a semantic version
+fn compare_versions(version: &str, other: &str) -> Option<Ordering> {
+    let parse = |version: &str| {
+        let version = version.trim();
+        semver::Version::parse(version.strip_prefix('v').unwrap_or(version)).ok()
+    };
+    Some(parse(version)?.cmp_precedence(&parse(other)?))
+}
+
+/// Version of running firmware according to platform
+#[cfg(feature = "esp-idf")]
+pub(crate) fn running_version() -> Option<String> {
+    crate::esp::system::app_version()
+}
+
+#[cfg(not(feature = "esp-idf"))]
+pub(crate) fn running_version() -> Option<String> {
+    None
+}
+
+#[derive(Serialize)]
+struct DeviceShadow<'a> {
+    firmware_version: &'a str,
+    sdk_version: &'static str,
+}
+
+impl<T: Transport> ByteBeamClient<T> {
+    /// Publish version of running firmware to [`DEVICE_SHADOW`] stream, if it is known
+    pub(crate) fn report_firmware_version(&self) {
+        let Some(firmware_version) = self.firmware_version() else {
+            return;
+        };
+
+        let payload = DeviceShadow {
+            firmware_version,
+            sdk_version: env!("CARGO_PKG_VERSION"),
+        };
+        if let Err(e) = self.publish(DEVICE_SHADOW, payload) {
+            error!("Failed to report firmware version: {e}");
+        }
+    }
+}
+
+/// Image of a firmware update, read as a stream
+pub trait FirmwareSource {
+    /// Start reading image from byte `offset`, again if reading failed earlier
+    ///
+    /// Returns offset from which image is actually read, which is `0` for sources that can't
+    /// start midway.
+    fn open(&mut self, offset: u64) -> Result<u64>;
+
+    /// Read next bytes of image into `buf`, returns `0` once image ends
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize>;
+}
+
+/// Partition into which an image of a firmware update is written
+///
+/// Image is written in order, in blocks of 4096 bytes except for the last one.
+pub trait FirmwareSink {
+    /// Prepare to write an image of `len` bytes from scratch
+    fn erase(&mut self, len: u64) -> Result<()>;
+
+    /// Read back part of image written at `offset` before a reset
+    fn read(&mut self, offset: u64, buf: &mut [u8]) -> Result<()>;
+
+    /// Write `data` of image at `offset`
+    fn write(&mut self, offset: u64, data: &[u8]) -> Result<()>;
+
+    /// Boot into the complete, verified image on next reset
+    fn activate(&mut self) -> Result<()>;
+}
+
+/// Image held in memory, to try out updates on host
+pub struct MemorySource {
+    image: Vec<u8>,
+    position: usize,
+    ranges: bool,
+    /// Positions at which reading fails once, ascending
+    interruptions: Vec<u64>,
+}
+
+impl MemorySource {
+    pub fn new(image: Vec<u8>) -> Self {
+        MemorySource {
+            image,
+            position: 0,
+            ranges: true,
+            interruptions: Vec::new(),
+        }
+    }
+
+    /// Always read image from start, like servers which don't support range requests
+    pub fn without_ranges(mut self) -> Self {
+        self.ranges = false;
+        self
+    }
+
+    /// Fail reading once `position` bytes of image are read, as if connection dropped there
+    pub fn interrupt_at(mut self, position: u64) -> Self {
+        self.interruptions.push(position);
+        self.interruptions.sort_unstable();
+        self
+    }
+}
+
+impl FirmwareSource for MemorySource {
+    fn open(&mut self, offset: u64) -> Result<u64> {
+        let offset = if self.ranges { offset } else { 0 };
+        self.position = (offset as usize).min(self.image.len());
+        Ok(self.position as u64)
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let position = self.position as u64;
+        let mut end = self.image.len().min(self.position + buf.len());
+        if let Some(&interruption) = self.interruptions.first() {
+            if interruption <= position {
+                self.interruptions.remove(0);
+                return Err(ByteBeamError::ota("connection dropped"));
+            }
+            end = end.min(interruption as usize);
+        }
+
+        let len = end - self.position;
+        buf[..len].copy_from_slice(&self.image[self.position..end]);
+        self.position = end;
+        Ok(len)
+    }
+}
+
+/// Partition held in memory, to try out updates on host
+#[derive(Clone, Debug, Default)]
+pub struct MemorySink {
+    image: Vec<u8>,
+    activated: bool,
+}
+
+impl MemorySink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Image written so far
+    pub fn image(&self) -> &[u8] {
+        &self.image
+    }
+
+    /// Whether device would boot into image
+    pub fn is_activated(&self) -> bool {
+        self.activated
+    }
+}
+
+impl FirmwareSink for MemorySink {
+    fn erase(&mut self, len: u64) -> Result<()> {
+        self.image = vec![0xff; len as usize];
+        self.activated = false;
+        Ok(())
+    }
+
+    fn read(&mut self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        let offset = offset as usize;
+        let data = self
+            .image
+            .get(offset..offset + buf.len())
+            .ok_or_else(|| ByteBeamError::ota("read beyond end of partition"))?;
+        buf.copy_from_slice(data);
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u64, data: &[u8]) -> Result<()> {
+        let offset = offset as usize;
+        if self.image.len() < offset + data.len() {
+            self.image.resize(offset + data.len(), 0xff);
+        }
+        self.image[offset..offset + data.len()].copy_from_slice(data);
+        Ok(())
+    }
+
+    fn activate(&mut self) -> Result<()> {
+        self.activated = true;
+        Ok(())
+    }
+}
+
+/// Image being downloaded into update partition, and how much of it is already written
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Download {
+    pub version: String,
+    pub content_length: u64,
+    pub checksum: Option<String>,
+    /// Bytes of image written to flash so far
+    pub offset: u64,
+}
+
+impl Download {
+    /// Download stored in `store`, if any
+    pub fn load(store: &mut (dyn BlobStore + '_)) -> Option<Download> {
+        match store.load() {
+            // cleared download is stored as an empty blob
+            Ok(Some(download)) if download.is_empty() => None,
+            Ok(Some(download)) => serde_json::from_slice(&download)
+                .map_err(|e| warn!("Ignoring corrupted download progress: {e}"))
+                .ok(),
+            Ok(None) => None,
+            Err(e) => {
+                warn!("Failed to load download progress: {e}");
+                None
+            }
+        }
+    }
+
+    /// Whether `self` continues download of the same image as `other`
+    pub fn resumes(&self, other: &Download) -> bool {
+        self.version == other.version
+            && self.content_length == other.content_length
+            && self.checksum == other.checksum
+            && self.offset <= self.content_length
+    }
+
+    pub fn save(&self, store: &mut (dyn BlobStore + '_)) {
+        let result = serde_json::to_vec(self)
+            .map_err(ByteBeamError::Serialization)
+            .and_then(|download| store.store(&download));
+        if let Err(e) = result {
+            warn!("Failed to store download progress: {e}");
+        }
+    }
+
+    pub fn clear(store: &mut (dyn BlobStore + '_)) {
+        if let Err(e) = store.store(&[]) {
+            warn!("Failed to clear download progress: {e}");
+        }
+    }
+}
+
+/// Why a download stopped before reaching end of image
+enum Failure {
+    /// Reading image failed, download can continue from where it stopped
+    Interrupted(ByteBeamError),
+    /// Update can't succeed, retrying won't help
+    Fatal(ByteBeamError),
+}
+
+/// State of an update being downloaded
+struct Downloader<'a> {
+    download: Download,
+    /// Fed with image as it is written, so that it always matches what is in sink
+    verifier: ImageVerifier,
+    sink: &'a mut dyn FirmwareSink,
+    store: Option<&'a mut (dyn BlobStore + 'static)>,
+    /// Image waiting to be written to sink
+    block: Vec<u8>,
+    filled: usize,
+}
+
+impl Downloader<'_> {
+    /// Continue download stored earlier if it is of the same image, else start over
+    fn resume_or_erase(&mut self) -> Result<()> {
+        let stored = self
+            .store
+            .as_deref_mut()
+            .and_then(Download::load)
+            .filter(|stored| stored.resumes(&self.download));
+        if let Some(stored) = stored {
+            info!("resuming download from {} bytes", stored.offset);
+            let mut offset = 0;
+            while offset < stored.offset {
+                let len = (stored.offset - offset).min(BLOCK_SIZE as u64) as usize;
+                self.sink.read(offset, &mut self.block[..len])?;
+                self.verifier.update(&self.block[..len])?;
+                offset += len as u64;
+            }
+            self.download.offset = stored.offset;
+            return Ok(());
+        }
+
+        info!("erasing update partition");
+        self.sink.erase(self.download.content_length)?;
+        self.save();
+        Ok(())
+    }
+
+    /// Download rest of image from `source`
+    fn fetch<T: Transport>(
+        &mut self,
+        source: &mut dyn FirmwareSource,
+        ctx: &ActionContext<T>,
+    ) -> Result<(), Failure> {
+        // block is discarded, so that it is downloaded again
+        let offset = self.download.offset;
+        self.filled = 0;
+        let start = source.open(offset).map_err(Failure::Interrupted)?;
+        if start > offset {
+            return Err(Failure::Fatal(ByteBeamError::ota(format!(
+                "source skipped to {start} bytes, expected {offset}"
+            ))));
+        }
+        // bytes to drop from start of image, when source can't start at offset
+        let mut skip = offset - start;
+
+        let content_length = self.download.content_length;
+        let mut buf = [0; 512];
+        let mut logged = offset * 10 / content_length.max(1);
+        while self.download.offset + (self.filled as u64) < content_length {
+            if ctx.is_cancelled() {
+                return Err(Failure::Interrupted(ByteBeamError::ota(
+                    "download cancelled",
+                )));
+            }
+
+            let len = buf.len().min(BLOCK_SIZE - self.filled);
+            let len_read = source.read(&mut buf[..len]).map_err(Failure::Interrupted)?;
+            if len_read == 0 {
+                return Err(Failure::Interrupted(ByteBeamError::ota(format!(
+                    "image ended at {} bytes",
+                    self.download.offset + self.filled as u64
+                ))));
+            }
+
+            let skipped = skip.min(len_read as u64) as usize;
+            skip -= skipped as u64;
+            let read = &buf[skipped..len_read];
+            self.block[self.filled..self.filled + read.len()].copy_from_slice(read);
+            self.filled += read.len();
+
+            let done = self.download.offset + self.filled as u64 >= content_length;
+            if self.filled == BLOCK_SIZE || done {
+                self.write_block().map_err(Failure::Fatal)?;
+
+                let percentage = self.download.offset * 100 / content_length;
+                if percentage / 10 > logged {
+                    info!("{percentage}% done");
+                    logged = percentage / 10;
+                }
+                if let Err(e) = ctx.progress(percentage as u32) {
+                    warn!("Failed to report progress of OTA: {e}");
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_block(&mut self) -> Result<()> {
+        let block = &self.block[..self.filled];
+        self.verifier.update(block)?;
+        self.sink.write(self.download.offset, block)?;
+
+        let checkpoint = self.download.offset / CHECKPOINT_INTERVAL;
+        self.download.offset += self.filled as u64;
+        self.filled = 0;
+        if self.download.offset / CHECKPOINT_INTERVAL > checkpoint {
+            self.save();
+        }
+
+        Ok(())
+    }
+
+    fn save(&mut self) {
+        if let Some(store) = self.store.as_deref_mut() {
+            self.download.save(store);
+        }
+    }
+
+    /// Forget stored download, so that next update starts from scratch
+    fn clear(&mut self) {
+        if let Some(store) = self.store.as_deref_mut() {
+            Download::clear(store);
+        }
+    }
+}
+
+/// Checks image of a firmware update as it is downloaded
+///
+/// # Example
+/// ```
+/// use bytebeam_esp_rs::firmware::ImageVerifier;
+///
+/// let image = b"firmware image";
+/// let mut verifier = ImageVerifier::new(Some(image.len() as u64))
+///     .sha256("1df2f3853d10a305aa52d36fd4a03f5721d7ce7daef6f7e5e8d51074d31361f1")?;
+/// for chunk in image.chunks(4) {
+///     verifier.update(chunk)?;
+/// }
+/// assert!(verifier.finish().is_ok());
+/// # Ok::<(), bytebeam_esp_rs::ByteBeamError>(())
+/// ```
+pub struct ImageVerifier {
+    hasher: Sha256,
+    received: u64,
+    content_length: Option<u64>,
+    sha256: Option<[u8; 32]>,
+    #[cfg(feature = "signature")]
+    signature: Option<(ed25519_dalek::VerifyingKey, ed25519_dalek::Signature)>,
+}
+
+impl ImageVerifier {
+    /// Verifier of an image which should be `content_length` bytes long, if known
+    pub fn new(content_length: Option<u64>) -> Self {
+        ImageVerifier {
+            hasher: Sha256::new(),
+            received: 0,
+            content_length,
+            sha256: None,
+            #[cfg(feature = "signature")]
+            signature: None,
+        }
+    }
+
+    /// Verifier of image of an update, applying checks required by `options`
+    pub fn for_update(
+        options: &OtaOptions,
+        content_length: Option<u64>,
+        checksum: Option<&str>,
+        #[allow(unused_variables)] signature: Option<&str>,
+    ) -> Result<Self> {
+        let mut verifier = ImageVerifier::new(content_length);
+        match checksum {
+            Some(checksum) => verifier = verifier.sha256(checksum)?,
+            None if options.require_checksum => {
+                return Err(ByteBeamError::ota("update doesn't have a checksum"))
+            }
+            None => {}
+        }
+
+        #[cfg(feature = "signature")]
+        if let Some(public_key) = &options.public_key {
+            let signature = signature.ok_or_else(|| ByteBeamError::ota("update isn't signed"))?;
+            verifier = verifier.signature(public_key, signature)?;
+        }
+
+        Ok(verifier)
+    }
+
+    /// Expect SHA-256 digest of image to be `checksum`, in hex
+    pub fn sha256(mut self, checksum: &str) -> Result<Self> {
+        let sha256 =
+            decode_hex(checksum).ok_or_else(|| ByteBeamError::ota("invalid SHA-256 checksum"))?;
+        self.sha256 = Some(sha256);
+        Ok(self)
+    }
+
+    /// Expect `signature`, in hex, to be an Ed25519 signature of SHA-256 digest of image made
+    /// with private key of `public_key`
+    ///
+    /// Requires `signature` feature.
+    #[cfg(feature = "signature")]
+    pub fn signature(mut self, public_key: &[u8; 32], signature: &str) -> Result<Self> {
+        let public_key = ed25519_dalek::VerifyingKey::from_bytes(public_key)
+            .map_err(|e| ByteBeamError::ota(format!("invalid public key: {e}")))?;
+        let signature =
+            decode_hex::<64>(signature).ok_or_else(|| ByteBeamError::ota("invalid signature"))?;
+        self.signature = Some((public_key, ed25519_dalek::Signature::from_bytes(&signature)));
+        Ok(self)
+    }
+
+    /// Number of bytes of image received so far
+    pub fn received(&self) -> u64 {
+        self.received
+    }
+
+    /// Add next `chunk` of image, failing if image grows beyond its content length
+    pub fn update(&mut self, chunk: &[u8]) -> Result<()> {
+        self.received += chunk.len() as u64;
+        if let Some(content_length) = self.content_length {
+            if self.received > content_length {
+                return Err(ByteBeamError::ota(format!(
+                    "image is larger than {content_length} bytes"
+                )));
+            }
+        }
+
+        self.hasher.update(chunk);
+        Ok(())
+    }
+
+    /// Check complete image, it must not be booted into if this fails
+    pub fn finish(self) -> Result<()> {
+        if let Some(content_length) = self.content_length {
+            if self.received != content_length {
+                return Err(ByteBeamError::ota(format!(
+                    "received {} bytes of image, expected {content_length}",
+                    self.received
+                )));
+            }
+        }
+
+        let digest: [u8; 32] = self.hasher.finalize().into();
+        if let Some(sha256) = self.sha256 {
+            if digest != sha256 {
+                return Err(ByteBeamError::ota(
+                    "SHA-256 checksum of image doesn't match",
+                ));
+            }
+        }
+
+        #[cfg(feature = "signature")]
+        if let Some((public_key, signature)) = self.signature {
+            public_key
+                .verify_strict(&digest, &signature)
+                .map_err(|_| ByteBeamError::ota("signature of image is invalid"))?;
+        }
+
+        Ok(())
+    }
+}
+
+fn decode_hex<const N: usize>(hex: &str) -> Option<[u8; N]> {
+    let hex = hex.trim().as_bytes();
+    if hex.len() != N * 2 {
+        return None;
+    }
+
+    let mut bytes = [0; N];
+    for (byte, pair) in bytes.iter_mut().zip(hex.chunks(2)) {
+        let pair = std::str::from_utf8(pair).ok()?;
+        *byte = u8::from_str_radix(pair, 16).ok()?;
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::{self, Receiver};
+
+    use super::*;
+    use crate::{
+        store::File,
+        transport::{
+            testing::{action_states, device_config, send_action, wait_for},
+            MockBroker, MockTransport,
+        },
+        ByteBeamClientBuilder, ConnectionState,
+    };
+
+    const IMAGE_LEN: usize = 3 * BLOCK_SIZE + 100;
+
+    fn image() -> Vec<u8> {
+        (0..IMAGE_LEN).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+
+    /// Client running firmware 1.0.0, with "update_firmware" handler installing images from
+    /// `source` into a sink which keeps its image across updates, like flash
+    struct Device {
+        broker: MockBroker,
+        results: Receiver<(Result<Installed>, MemorySink)>,
+    }
+
+    impl Device {
+        fn new(options: OtaOptions, source: impl FnMut() -> MemorySource + Send + 'static) -> Self {
+            Self::with_builder(ByteBeamClient::builder(), options, source)
+        }
+
+        fn with_builder(
+            builder: ByteBeamClientBuilder,
+            options: OtaOptions,
+            mut source: impl FnMut() -> MemorySource + Send + 'static,
+        ) -> Self {
+            let (transport, events, broker) = MockTransport::new();
+            let client = builder
+                .device_config(device_config())
+                .firmware_version("1.0.0")
+                .connect_with(transport, events)
+                .unwrap();
+
+            let (tx, results) = mpsc::channel();
+            let mut sink = MemorySink::new();
+            client.register_typed_action(
+                "update_firmware".into(),
+                move |update: FirmwareUpdate, ctx| {
+                    let result = update.install(&options, &mut source(), &mut sink, &ctx);
+                    tx.send((result, sink.clone())).unwrap();
+                },
+            );
+            broker.connect();
+            wait_for(|| client.connection_state() == ConnectionState::Connected);
+
+            Device { broker, results }
+        }
+
+        fn update(&self, id: &str, version: &str) {
+            let update = serde_json::json!({
+                "url": "https://example.com/firmware.bin",
+                "version": version,
+                "content-length": IMAGE_LEN,
+                "checksum": hex(&Sha256::digest(image())),
+            });
+            send_action(&self.broker, id, "update_firmware", &update.to_string());
+        }
+
+        fn result(&self) -> (Result<Installed>, MemorySink) {
+            self.results.recv_timeout(Duration::from_secs(5)).unwrap()
+        }
+    }
+
+    /// Outcome of feeding `image` to `verifier` in chunks
+    fn verified(verifier: Result<ImageVerifier>, image: &[u8]) -> Result<()> {
+        let mut verifier = verifier?;
+        for chunk in image.chunks(1000) {
+            verifier.update(chunk)?;
+        }
+        verifier.finish()
+    }
+
+    #[test]
+    fn verifier_accepts_intact_image() {
+        let checksum = hex(&Sha256::digest(image()));
+        let verifier = ImageVerifier::new(Some(IMAGE_LEN as u64)).sha256(&checksum);
+        assert!(verified(verifier, &image()).is_ok());
+        assert!(verified(Ok(ImageVerifier::new(None)), &image()).is_ok());
+    }
+
+    #[test]
+    fn verifier_rejects_size_mismatch() {
+        let mut image = image();
+        let verifier = ImageVerifier::new(Some(IMAGE_LEN as u64 - 1));
+        assert!(verified(Ok(verifier), &image).is_err());
+
+        image.pop();
+        let verifier = ImageVerifier::new(Some(IMAGE_LEN as u64));
+        assert!(verified(Ok(verifier), &image).is_err());
+    }
+
+    #[test]
+    fn verifier_rejects_bad_checksum() {
+        let mut checksum = Sha256::digest(image());
+        checksum[0] ^= 1;
+        let verifier = ImageVerifier::new(None).sha256(&hex(&checksum));
+        assert!(verified(verifier, &image()).is_err());
+
+        for malformed in ["", "1df2", &"z".repeat(64), &"0".repeat(66)] {
+            assert!(ImageVerifier::new(None).sha256(malformed).is_err());
+        }
+    }
+
+    #[test]
+    fn checksum_is_required_by_options() {
+        let options = OtaOptions {
+            require_checksum: true,
+            ..Default::default()
+        };
+        let len = Some(IMAGE_LEN as u64);
+        assert!(ImageVerifier::for_update(&options, len, None, None).is_err());
+
+        let checksum = hex(&Sha256::digest(image()));
+        let verifier = ImageVerifier::for_update(&options, len, Some(&checksum), None);
+        assert!(verified(verifier, &image()).is_ok());
+
+        let options = OtaOptions::default();
+        let verifier = ImageVerifier::for_update(&options, len, None, None);
+        assert!(verified(verifier, &image()).is_ok());
+    }
+
+    #[cfg(feature = "signature")]
+    #[test]
+    fn verifier_checks_signature() {
+        use ed25519_dalek::{Signer, SigningKey};
+
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let options = OtaOptions {
+            public_key: Some(key.verifying_key().to_bytes()),
+            ..Default::default()
+        };
+        let sign = |image: &[u8]| hex(&key.sign(&Sha256::digest(image)).to_bytes());
+        let len = Some(IMAGE_LEN as u64);
+
+        let signature = sign(&image());
+        let verifier = ImageVerifier::for_update(&options, len, None, Some(&signature));
+        assert!(verified(verifier, &image()).is_ok());
+
+        let mut tampered = image();
+        tampered[0] ^= 1;
+        let verifier = ImageVerifier::for_update(&options, len, None, Some(&signature));
+        assert!(verified(verifier, &tampered).is_err());
+
+        let signature = sign(&tampered);
+        let verifier = ImageVerifier::for_update(&options, len, None, Some(&signature));
+        assert!(verified(verifier, &image()).is_err());
+
+        assert!(ImageVerifier::for_update(&options, len, None, None).is_err());
+        assert!(ImageVerifier::for_update(&options, len, None, Some("00")).is_err());
+    }
+
+    #[test]
+    fn completes_before_reset_without_journal_store() {
+        let device = Device::new(OtaOptions::default(), || MemorySource::new(image()));
+        device.update("1", "2.0.0");
+        let (result, sink) = device.result();
+        assert!(matches!(result, Ok(Installed::PendingReset)));
+        assert!(sink.is_activated());
+        assert_eq!(
+            action_states(&device.broker, "1").last().unwrap(),
+            "Completed"
+        );
+    }
+
+    #[test]
+    fn cancelling_stops_retry_wait() {
+        let options = OtaOptions {
+            retry_delay: Duration::from_secs(60),
+            ..Default::default()
+        };
+        let device = Device::new(options, || {
+            MemorySource::new(image()).interrupt_at(BLOCK_SIZE as u64 + 100)
+        });
+        device.update("1", "2.0.0");
+        wait_for(|| !action_states(&device.broker, "1").is_empty());
+
+        let cancel = serde_json::json!({"action_id": "1", "name": "update_firmware"});
+        send_action(&device.broker, "2", "cancel_action", &cancel.to_string());
+        let (result, sink) = device.result();
+        assert!(result.is_err());
+        assert!(!sink.is_activated());
+        wait_for(|| action_states(&device.broker, "1").last().unwrap() == "Cancelled");
+    }
+
+    /// Options which retry right away
+    fn quick_retries(retries: u32) -> OtaOptions {
+        OtaOptions {
+            retries,
+            retry_delay: Duration::from_millis(10),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn installs_clean_image() {
+        let device = Device::new(OtaOptions::default(), || MemorySource::new(image()));
+        device.update("1", "2.0.0");
+        let (result, sink) = device.result();
+        assert_eq!(result.unwrap(), Installed::PendingReset);
+        assert_eq!(sink.image(), image());
+        assert!(sink.is_activated());
+    }
+
+    #[test]
+    fn resumes_interrupted_download() {
+        let path = std::env::temp_dir().join(format!("download_{}", std::process::id()));
+        std::fs::remove_file(&path).ok();
+        let interrupted_at = 2 * BLOCK_SIZE;
+        let mut attempts = 0;
+        let device = Device::with_builder(
+            ByteBeamClient::builder().download_store(File::new(&path)),
+            quick_retries(0),
+            move || {
+                attempts += 1;
+                if attempts == 1 {
+                    MemorySource::new(image()).interrupt_at(interrupted_at as u64 + 100)
+                } else {
+                    // resumed download never reads what was written before interruption
+                    let mut image = image();
+                    image[..interrupted_at].fill(0);
+                    MemorySource::new(image)
+                }
+            },
+        );
+
+        device.update("1", "2.0.0");
+        let (result, sink) = device.result();
+        assert!(result.is_err());
+        assert!(!sink.is_activated());
+
+        device.update("2", "2.0.0");
+        let (result, sink) = device.result();
+        assert_eq!(result.unwrap(), Installed::PendingReset);
+        assert_eq!(sink.image(), image());
+        assert!(sink.is_activated());
+    }
+
+    #[test]
+    fn restarts_download_without_range_support() {
+        let device = Device::new(quick_retries(1), || {
+            MemorySource::new(image())
+                .without_ranges()
+                .interrupt_at(2 * BLOCK_SIZE as u64 + 100)
+        });
+        device.update("1", "2.0.0");
+        let (result, sink) = device.result();
+        assert_eq!(result.unwrap(), Installed::PendingReset);
+        assert_eq!(sink.image(), image());
+        assert!(sink.is_activated());
+    }
+
+    #[test]
+    fn fails_once_retries_run_out() {
+        // drops four times at the same position, only the first drop comes after progress
+        let flaky = || {
+            (0..4).fold(MemorySource::new(image()), |source, _| {
+                source.interrupt_at(BLOCK_SIZE as u64 + 100)
+            })
+        };
+
+        let device = Device::new(quick_retries(3), flaky);
+        device.update("1", "2.0.0");
+        let (result, sink) = device.result();
+        assert!(result.is_err());
+        assert!(!sink.is_activated());
+
+        let device = Device::new(quick_retries(4), flaky);
+        device.update("1", "2.0.0");
+        assert_eq!(device.result().0.unwrap(), Installed::PendingReset);
+    }
+
+    /// Outcome of updating firmware 1.0.0 to `version` with `policy`
+    fn update_to(version: &str, policy: VersionPolicy) -> (Result<Installed>, Vec<String>) {
+        let options = OtaOptions {
+            version_policy: policy,
+            ..Default::default()
+        };
+        let device = Device::new(options, || MemorySource::new(image()));
+        device.update("1", version);
+        let (result, sink) = device.result();
+        assert_eq!(
+            sink.is_activated(),
+            matches!(result, Ok(Installed::PendingReset))
+        );
+        wait_for(|| !action_states(&device.broker, "1").is_empty());
+        (result, action_states(&device.broker, "1"))
+    }
+
+    #[test]
+    fn upgrade_only_installs_newer_versions() {
+        let (result, states) = update_to("1.0.0", VersionPolicy::UpgradeOnly);
+        assert_eq!(result.unwrap(), Installed::AlreadyRunning);
+        assert_eq!(states, ["Completed"]);
+
+        let (result, _) = update_to("0.9.0", VersionPolicy::UpgradeOnly);
+        assert!(result.is_err());
+
+        let (result, _) = update_to("v1.1.0", VersionPolicy::UpgradeOnly);
+        assert_eq!(result.unwrap(), Installed::PendingReset);
+    }
+
+    #[test]
+    fn skip_same_installs_other_versions() {
+        let (result, states) = update_to("1.0.0", VersionPolicy::SkipSame);
+        assert_eq!(result.unwrap(), Installed::AlreadyRunning);
+        assert_eq!(states, ["Completed"]);
+
+        let (result, _) = update_to("0.9.0", VersionPolicy::SkipSame);
+        assert_eq!(result.unwrap(), Installed::PendingReset);
+
+        let (result, _) = update_to("nightly", VersionPolicy::SkipSame);
+        assert_eq!(result.unwrap(), Installed::PendingReset);
+    }
+
+    #[test]
+    fn force_reinstalls_running_version() {
+        let (result, _) = update_to("1.0.0", VersionPolicy::Force);
+        assert_eq!(result.unwrap(), Installed::PendingReset);
+
+        let (result, _) = update_to("0.9.0", VersionPolicy::Force);
+        assert_eq!(result.unwrap(), Installed::PendingReset);
+    }
+}
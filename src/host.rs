@@ -0,0 +1,183 @@
+//! Host ( Linux, macOS, ... ) backend of the SDK, built on top of [`rumqttc`]
+//!
+//! Useful for simulating devices on a development machine, against Bytebeam cloud or a local
+//! broker like Mosquitto.
+use std::{sync::Arc, time::Duration};
+
+use log::info;
+use rumqttc::{
+    Client, ClientError, Connection, ConnectionError, Event, MqttOptions, Packet, TlsConfiguration,
+};
+
+use crate::{
+    builder::{ByteBeamClientBuilder, ClientOptions},
+    config::File,
+    error::Result,
+    transport::{EventStream, QoS, Transport, TransportEvent},
+    ByteBeamClient, ByteBeamError, DeviceConfig,
+};
+
+/// Transport backed by [`rumqttc`]'s synchronous client
+pub struct HostMqttTransport {
+    client: Client,
+}
+
+impl HostMqttTransport {
+    /// Connect with broker mentioned in `device_config`
+    pub fn connect(
+        device_config: &DeviceConfig,
+        client_options: &ClientOptions,
+    ) -> Result<(Self, HostMqttEvents)> {
+        let auth = &device_config.authentication;
+        let client_id = client_options
+            .client_id
+            .as_deref()
+            .unwrap_or(&device_config.device_id);
+        let port = device_config.port.try_into().map_err(|_| {
+            ByteBeamError::ConnectionRefused(format!("invalid port {}", device_config.port).into())
+        })?;
+        let mut options = MqttOptions::new(client_id, &device_config.broker, port);
+        options.set_keep_alive(client_options.keep_alive.unwrap_or(Duration::from_secs(30)));
+        let packet_size = options.max_packet_size();
+        options.set_max_packet_size(
+            client_options.buffer_size.unwrap_or(packet_size),
+            client_options.out_buffer_size.unwrap_or(packet_size),
+        );
+        options.set_transport(rumqttc::Transport::Tls(TlsConfiguration::Simple {
+            ca: auth.ca_certificate.as_bytes().to_vec(),
+            alpn: None,
+            client_auth: Some((
+                auth.device_certificate.as_bytes().to_vec(),
+                auth.device_private_key.as_bytes().to_vec(),
+            )),
+        }));
+
+        let (client, connection) = Client::new(options, 10);
+        let events = HostMqttEvents {
+            connection,
+            disconnected: false,
+        };
+
+        Ok((HostMqttTransport { client }, events))
+    }
+}
+
+impl Transport for HostMqttTransport {
+    /// Message ids aren't known till message is actually sent, so this always returns `0`
+    fn publish(&mut self, topic: &str, qos: QoS, payload: &[u8]) -> Result<u32> {
+        self.client
+            .try_publish(topic, qos.into(), false, payload)
+            .map_err(client_error)?;
+        Ok(0)
+    }
+
+    fn subscribe(&mut self, topic: &str, qos: QoS) -> Result<u32> {
+        self.client
+            .try_subscribe(topic, qos.into())
+            .map_err(client_error)?;
+        Ok(0)
+    }
+}
+
+fn client_error(e: ClientError) -> ByteBeamError {
+    match e {
+        ClientError::TryRequest(_) => ByteBeamError::QueueFull,
+        e => ByteBeamError::transport(e),
+    }
+}
+
+fn connection_error(e: ConnectionError) -> ByteBeamError {
+    match e {
+        ConnectionError::Tls(e) => ByteBeamError::Tls(e.into()),
+        e @ (ConnectionError::ConnectionRefused(_) | ConnectionError::Io(_)) => {
+            ByteBeamError::ConnectionRefused(e.into())
+        }
+        e => ByteBeamError::transport(e),
+    }
+}
+
+/// Events of a [`HostMqttTransport`]
+///
+/// Polling it drives the underlying connection, which reconnects on the next poll after an error.
+/// Every error is followed by [`TransportEvent::Disconnected`], so that client backs off before
+/// polling again.
+pub struct HostMqttEvents {
+    connection: Connection,
+    disconnected: bool,
+}
+
+impl EventStream for HostMqttEvents {
+    fn next_event(&mut self) -> Option<TransportEvent> {
+        if std::mem::take(&mut self.disconnected) {
+            return Some(TransportEvent::Disconnected);
+        }
+
+        loop {
+            match self.connection.recv().ok()? {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    return Some(TransportEvent::Connected);
+                }
+                Ok(Event::Incoming(Packet::Publish(publish))) => {
+                    return Some(TransportEvent::Received {
+                        topic: publish.topic,
+                        payload: publish.payload.to_vec(),
+                    })
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    self.disconnected = true;
+                    return Some(TransportEvent::Error(connection_error(e)));
+                }
+            }
+        }
+    }
+}
+
+impl From<QoS> for rumqttc::QoS {
+    fn from(qos: QoS) -> Self {
+        match qos {
+            QoS::AtMostOnce => Self::AtMostOnce,
+            QoS::AtLeastOnce => Self::AtLeastOnce,
+            QoS::ExactlyOnce => Self::ExactlyOnce,
+        }
+    }
+}
+
+impl ByteBeamClient<HostMqttTransport> {
+    /// Connect with Bytebeam cloud using given `device_config`
+    ///
+    /// # Example
+    /// ```no_run
+    /// use bytebeam_esp_rs::{ByteBeamClient, DeviceConfig};
+    ///
+    /// let config = std::fs::read("device_config.json")?;
+    /// let bytebeam_client = ByteBeamClient::connect(DeviceConfig::from_slice(&config)?)?;
+    /// # anyhow::Ok(())
+    /// ```
+    pub fn connect(device_config: DeviceConfig) -> Result<Arc<Self>> {
+        ByteBeamClientBuilder::default()
+            .device_config(device_config)
+            .connect()
+    }
+}
+
+impl ByteBeamClientBuilder {
+    /// Connect with Bytebeam cloud
+    ///
+    /// Reads `device_config.json` from current directory if neither device config nor config
+    /// source was set
+    pub fn connect(mut self) -> Result<Arc<ByteBeamClient<HostMqttTransport>>> {
+        let device_config = self.load_device_config(File::new("device_config.json"))?;
+        let (transport, events) = HostMqttTransport::connect(&device_config, &self.options)?;
+        info!("connecting to {}", device_config.broker);
+
+        ByteBeamClient::start(
+            device_config.device_id,
+            device_config.project_id,
+            self.options,
+            self.extensions,
+            transport,
+            events,
+        )
+    }
+}
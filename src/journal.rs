@@ -0,0 +1,337 @@
+//! Journal of actions received from cloud, used to skip redelivered actions
+//!
+//! Client remembers ids of the most recent actions, and ignores an action if it has seen its id
+//! before. With a [`BlobStore`], journal also survives reboots: actions which were running
+//! when device reset get a final status once client connects again. They are reported as failed,
+//! unless handler asked for them to be completed by a reset with
+//! [`ActionContext::complete_after_reset`](crate::ActionContext::complete_after_reset), as done
+//! by firmware updates. When new firmware waits for confirmation after an update, such actions
+//! are only reported once firmware passes its health check, and fail if it is rolled back.
+//!
+//! # Example
+//! ```no_run
+//! use bytebeam_esp_rs::{store, ByteBeamClient};
+//!
+//! let bytebeam_client = ByteBeamClient::builder()
+//!     .journal_store(store::File::new("/littlefs/actions.json"))
+//!     .connect()?;
+//! # anyhow::Ok(())
+//! ```
+use std::collections::VecDeque;
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::{store::BlobStore, ActionState, ByteBeamError};
+
+/// Number of actions remembered by journal
+const CAPACITY: usize = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum EntryState {
+    Running,
+    /// Running, and a reset means that it was completed
+    CompleteAfterReset,
+    /// Completed by a reset, but firmware it reset into was rolled back
+    RolledBack,
+    Finished,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct Entry {
+    pub id: String,
+    pub name: String,
+    state: EntryState,
+}
+
+impl Entry {
+    /// Status to report for action that was interrupted by a reset, with error if it failed
+    pub fn status_after_reset(&self) -> (ActionState, Option<&'static str>) {
+        match self.state {
+            EntryState::CompleteAfterReset => (ActionState::Completed, None),
+            EntryState::RolledBack => (
+                ActionState::Failed,
+                Some("firmware failed health check, rolled back"),
+            ),
+            _ => (ActionState::Failed, Some("interrupted by device reset")),
+        }
+    }
+}
+
+/// Recently received actions, oldest first
+pub(crate) struct ActionJournal {
+    entries: VecDeque<Entry>,
+    /// Actions interrupted by a reset, whose status is yet to be reported
+    interrupted: Vec<Entry>,
+    /// Hold back actions completed by a reset till firmware is confirmed
+    hold_completions: bool,
+    store: Option<Box<dyn BlobStore>>,
+}
+
+impl ActionJournal {
+    pub fn open(mut store: Option<Box<dyn BlobStore>>, hold_completions: bool) -> Self {
+        let entries: VecDeque<Entry> = match store.as_mut().map(|store| store.load()) {
+            Some(Ok(Some(journal))) => serde_json::from_slice(&journal).unwrap_or_else(|e| {
+                warn!("Ignoring corrupted action journal: {e}");
+                VecDeque::new()
+            }),
+            Some(Err(e)) => {
+                warn!("Failed to load action journal, starting over: {e}");
+                VecDeque::new()
+            }
+            _ => VecDeque::new(),
+        };
+        let interrupted = entries
+            .iter()
+            .filter(|entry| entry.state != EntryState::Finished)
+            .cloned()
+            .collect();
+
+        ActionJournal {
+            entries,
+            interrupted,
+            hold_completions,
+            store,
+        }
+    }
+
+    /// Record start of action `id`, returns `false` if it was seen before
+    pub fn start(&mut self, id: &str, name: &str) -> bool {
+        if self.entries.iter().any(|entry| entry.id == id) {
+            return false;
+        }
+
+        if self.entries.len() >= CAPACITY {
+            // prefer forgetting finished actions, so that interrupted ones are still reported
+            let oldest = self
+                .entries
+                .iter()
+                .position(|entry| entry.state == EntryState::Finished)
+                .unwrap_or(0);
+            self.entries.remove(oldest);
+        }
+        self.entries.push_back(Entry {
+            id: id.into(),
+            name: name.into(),
+            state: EntryState::Running,
+        });
+        self.save();
+
+        true
+    }
+
+    /// Record that a reset completes action `id`
+    pub fn complete_after_reset(&mut self, id: &str) {
+        self.set_state(id, EntryState::CompleteAfterReset)
+    }
+
+    /// Record that final status of action `id` was sent
+    pub fn finish(&mut self, id: &str) {
+        self.set_state(id, EntryState::Finished)
+    }
+
+    /// Actions interrupted by a reset, removed from the list so that they are reported only once
+    ///
+    /// Hand back the ones whose status couldn't be sent with [`requeue`](Self::requeue).
+    pub fn take_interrupted(&mut self) -> Vec<Entry> {
+        if !self.hold_completions {
+            return std::mem::take(&mut self.interrupted);
+        }
+
+        let (held, interrupted) = std::mem::take(&mut self.interrupted)
+            .into_iter()
+            .partition(|entry| entry.state == EntryState::CompleteAfterReset);
+        self.interrupted = held;
+        interrupted
+    }
+
+    /// Report interrupted action `entry` again next time, as sending its status failed
+    pub fn requeue(&mut self, entry: Entry) {
+        self.interrupted.push(entry);
+    }
+
+    /// Stop holding back actions completed by a reset, failing them if firmware was rolled back
+    #[cfg(feature = "esp-idf")]
+    pub fn release_completions(&mut self, firmware_valid: bool) {
+        self.hold_completions = false;
+        if firmware_valid {
+            return;
+        }
+
+        let completed = |entry: &&mut Entry| entry.state == EntryState::CompleteAfterReset;
+        for entry in self.interrupted.iter_mut().filter(completed) {
+            entry.state = EntryState::RolledBack;
+        }
+        for entry in self.entries.iter_mut().filter(completed) {
+            entry.state = EntryState::RolledBack;
+        }
+        self.save();
+    }
+
+    /// Whether journal survives a reset
+    pub fn is_persistent(&self) -> bool {
+        self.store.is_some()
+    }
+
+    fn set_state(&mut self, id: &str, state: EntryState) {
+        let Some(entry) = self.entries.iter_mut().find(|entry| entry.id == id) else {
+            return;
+        };
+        // final status is already sent, a reset can't change it anymore
+        if entry.state != state && entry.state != EntryState::Finished {
+            entry.state = state;
+            self.save();
+        }
+    }
+
+    fn save(&mut self) {
+        let Some(store) = &mut self.store else {
+            return;
+        };
+
+        let result = serde_json::to_vec(&self.entries)
+            .map_err(ByteBeamError::Serialization)
+            .and_then(|journal| store.store(&journal));
+        if let Err(e) = result {
+            warn!("Failed to store action journal: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::error::Result;
+
+    /// Store which outlives journals opened on it, like flash across a reset
+    #[derive(Clone, Default)]
+    struct Memory(Arc<Mutex<Option<Vec<u8>>>>);
+
+    impl BlobStore for Memory {
+        fn load(&mut self) -> Result<Option<Vec<u8>>> {
+            Ok(self.0.lock().unwrap().clone())
+        }
+
+        fn store(&mut self, journal: &[u8]) -> Result<()> {
+            *self.0.lock().unwrap() = Some(journal.to_vec());
+            Ok(())
+        }
+    }
+
+    fn open(store: &Memory, hold_completions: bool) -> ActionJournal {
+        ActionJournal::open(Some(Box::new(store.clone())), hold_completions)
+    }
+
+    fn statuses(entries: Vec<Entry>) -> Vec<(String, ActionState)> {
+        entries
+            .into_iter()
+            .map(|entry| (entry.id.clone(), entry.status_after_reset().0))
+            .collect()
+    }
+
+    #[test]
+    fn skips_redelivered_actions() {
+        let mut journal = ActionJournal::open(None, false);
+        assert!(journal.start("1", "reboot"));
+        assert!(!journal.start("1", "reboot"));
+        journal.finish("1");
+        assert!(!journal.start("1", "reboot"));
+        assert!(journal.start("2", "reboot"));
+    }
+
+    #[test]
+    fn forgets_oldest_finished_actions_first() {
+        let mut journal = ActionJournal::open(None, false);
+        journal.start("running", "update_firmware");
+        for id in 0..CAPACITY {
+            journal.start(&id.to_string(), "reboot");
+            journal.finish(&id.to_string());
+        }
+        assert!(!journal.start("running", "update_firmware"));
+        assert!(journal.start("0", "reboot"));
+    }
+
+    #[test]
+    fn reports_actions_interrupted_by_reset() {
+        let store = Memory::default();
+        let mut journal = open(&store, false);
+        journal.start("1", "calibrate");
+        journal.start("2", "update_firmware");
+        journal.complete_after_reset("2");
+        journal.start("3", "reboot");
+        journal.finish("3");
+
+        let mut journal = open(&store, false);
+        assert_eq!(
+            statuses(journal.take_interrupted()),
+            [
+                ("1".into(), ActionState::Failed),
+                ("2".into(), ActionState::Completed)
+            ]
+        );
+        assert!(journal.take_interrupted().is_empty());
+        // still remembered, so that they aren't run again if redelivered
+        assert!(!journal.start("2", "update_firmware"));
+    }
+
+    #[test]
+    fn reports_requeued_actions_again() {
+        let store = Memory::default();
+        let mut journal = open(&store, false);
+        journal.start("1", "calibrate");
+        journal.start("2", "reboot");
+
+        let mut journal = open(&store, false);
+        let mut interrupted = journal.take_interrupted();
+        journal.finish(&interrupted.remove(0).id);
+        journal.requeue(interrupted.remove(0));
+        assert_eq!(
+            statuses(journal.take_interrupted()),
+            [("2".into(), ActionState::Failed)]
+        );
+        assert!(journal.take_interrupted().is_empty());
+    }
+
+    #[test]
+    fn finished_action_stays_finished() {
+        let store = Memory::default();
+        let mut journal = open(&store, false);
+        journal.start("1", "update_firmware");
+        journal.finish("1");
+        journal.complete_after_reset("1");
+
+        assert!(open(&store, false).take_interrupted().is_empty());
+    }
+
+    #[test]
+    fn holds_completions_till_released() {
+        let store = Memory::default();
+        let mut journal = open(&store, false);
+        journal.start("1", "update_firmware");
+        journal.complete_after_reset("1");
+        journal.start("2", "calibrate");
+
+        let mut journal = open(&store, true);
+        assert_eq!(
+            statuses(journal.take_interrupted()),
+            [("2".into(), ActionState::Failed)]
+        );
+        journal.hold_completions = false;
+        assert_eq!(
+            statuses(journal.take_interrupted()),
+            [("1".into(), ActionState::Completed)]
+        );
+    }
+
+    #[test]
+    fn ignores_corrupted_store() {
+        let store = Memory::default();
+        store.clone().store(b"not a journal").unwrap();
+        let mut journal = open(&store, false);
+        assert!(journal.take_interrupted().is_empty());
+        assert!(journal.start("1", "reboot"));
+    }
+}
@@ -0,0 +1,271 @@
+//! Built-in handlers of housekeeping actions, enabled with
+//! [`ByteBeamClient::enable_device_management`]
+use std::{collections::BTreeMap, thread, time::Duration};
+
+use log::{error, info};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{transport::Transport, Action, ActionContext, ActionOptions, ByteBeamClient};
+
+/// Restarts device, after reporting action as completed
+///
+/// Only logged on host, a [`reset_hook`](crate::ByteBeamClientBuilder::reset_hook) is called
+/// instead if one is set.
+pub const REBOOT: &str = "reboot";
+/// Completes right away, responding with payload of action
+pub const PING: &str = "ping";
+/// Responds with firmware version, running partition, free heap and uptime
+pub const DEVICE_INFO: &str = "device_info";
+/// Stores settings in payload, an object of setting names and their values
+pub const SET_CONFIG: &str = "set_config";
+
+impl<T: Transport> ByteBeamClient<T> {
+    /// Register built-in handlers of [`REBOOT`], [`PING`], [`DEVICE_INFO`] and [`SET_CONFIG`]
+    /// actions
+    ///
+    /// Handlers registered for these actions earlier are replaced. Responses are published to
+    /// `action_responses` stream, see [`ActionContext::respond`]. [`SET_CONFIG`] needs a
+    /// [`settings_store`](crate::ByteBeamClientBuilder::settings_store) to work. [`REBOOT`] runs
+    /// with [`ActionOptions::SYSTEM_PRIORITY`], unless
+    /// options were set for it already.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use bytebeam_esp_rs::ByteBeamClient;
+    ///
+    /// let bytebeam_client = ByteBeamClient::builder().connect()?;
+    /// bytebeam_client.enable_device_management();
+    /// # anyhow::Ok(())
+    /// ```
+    pub fn enable_device_management(&self) {
+        self.register_action_handle(REBOOT.into(), reboot);
+        self.default_action_priority(REBOOT, ActionOptions::SYSTEM_PRIORITY);
+        self.register_action_handle(PING.into(), ping);
+        self.register_action_handle(DEVICE_INFO.into(), device_info);
+        self.register_typed_action(SET_CONFIG.into(), set_config);
+    }
+}
+
+fn reboot<T: Transport>(_action: Action, ctx: ActionContext<T>) {
+    if let Err(e) = ctx.complete() {
+        error!("Failed to report reboot: {e}");
+    }
+    info!("Rebooting in 1 sec...");
+    // give MQTT client a chance to send status
+    thread::sleep(Duration::from_secs(1));
+    ctx.client().restart_device(ctx.id());
+}
+
+fn ping<T: Transport>(action: Action, ctx: ActionContext<T>) {
+    let payload = action
+        .payload
+        .map(|payload| serde_json::from_str(&payload).unwrap_or(Value::String(payload)));
+    let result = ctx.respond(payload).and_then(|_| ctx.complete());
+    if let Err(e) = result {
+        ctx.fail(&[&e.to_string()]).ok();
+    }
+}
+
+fn device_info<T: Transport>(_action: Action, ctx: ActionContext<T>) {
+    let firmware_version = ctx.client().firmware_version().map(Into::into);
+    let result = ctx
+        .respond(DeviceInfo::read(firmware_version))
+        .and_then(|_| ctx.complete());
+    if let Err(e) = result {
+        ctx.fail(&[&e.to_string()]).ok();
+    }
+}
+
+fn set_config<T: Transport>(settings: BTreeMap<String, Value>, ctx: ActionContext<T>) {
+    let errors: Vec<_> = settings
+        .iter()
+        .filter_map(|(key, value)| {
+            info!("Setting {key} to {value}");
+            ctx.client()
+                .set_setting(key, value)
+                .err()
+                .map(|e| format!("{key}: {e}"))
+        })
+        .collect();
+
+    if errors.is_empty() {
+        ctx.complete().ok();
+    } else {
+        let errors: Vec<_> = errors.iter().map(String::as_str).collect();
+        ctx.fail(&errors).ok();
+    }
+}
+
+/// Response of [`DEVICE_INFO`] action, fields which platform can't tell are left out
+#[derive(Serialize)]
+struct DeviceInfo {
+    sdk_version: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    firmware_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    partition: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    free_heap: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uptime_secs: Option<u64>,
+}
+
+impl DeviceInfo {
+    #[cfg(feature = "esp-idf")]
+    fn read(firmware_version: Option<String>) -> Self {
+        use crate::esp::system;
+
+        let (partition, _) = system::running_firmware();
+        DeviceInfo {
+            sdk_version: env!("CARGO_PKG_VERSION"),
+            firmware_version,
+            partition,
+            free_heap: Some(system::free_heap()),
+            uptime_secs: Some(system::uptime().as_secs()),
+        }
+    }
+
+    #[cfg(not(feature = "esp-idf"))]
+    fn read(firmware_version: Option<String>) -> Self {
+        DeviceInfo {
+            sdk_version: env!("CARGO_PKG_VERSION"),
+            firmware_version,
+            partition: None,
+            free_heap: None,
+            uptime_secs: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        store,
+        transport::{
+            testing::{action_states, device_config, send_action, stream_messages, wait_for},
+            MockBroker, MockTransport,
+        },
+        ByteBeamClientBuilder, ResetReason,
+    };
+
+    fn connect(builder: ByteBeamClientBuilder) -> (Arc<ByteBeamClient<MockTransport>>, MockBroker) {
+        let (transport, events, broker) = MockTransport::new();
+        let client = builder
+            .device_config(device_config())
+            .connect_with(transport, events)
+            .unwrap();
+        client.enable_device_management();
+        broker.connect();
+
+        (client, broker)
+    }
+
+    /// Run action `name` with `payload` to its end, returning its states and responses
+    fn run(broker: &MockBroker, name: &str, payload: &str) -> (Vec<String>, Vec<Value>) {
+        send_action(broker, "1", name, payload);
+        wait_for(|| action_states(broker, "1").len() == 1);
+
+        let responses = stream_messages(broker, "action_responses")
+            .into_iter()
+            .flatten()
+            .filter(|response| response["action_id"] == "1")
+            .map(|response| response["response"].clone())
+            .collect();
+        (action_states(broker, "1"), responses)
+    }
+
+    fn priority(client: &ByteBeamClient<MockTransport>, action_name: &str) -> Option<u8> {
+        let options = client.action_options.lock().unwrap();
+        options.get(action_name).map(|options| options.priority)
+    }
+
+    #[test]
+    fn reboot_runs_with_system_priority() {
+        let (client, _broker) = connect(ByteBeamClient::builder());
+        assert_eq!(
+            priority(&client, REBOOT),
+            Some(ActionOptions::SYSTEM_PRIORITY)
+        );
+        assert_eq!(priority(&client, PING), None);
+    }
+
+    #[test]
+    fn keeps_options_set_earlier() {
+        let (transport, events, _broker) = MockTransport::new();
+        let client = ByteBeamClient::builder()
+            .device_config(device_config())
+            .connect_with(transport, events)
+            .unwrap();
+        let options = ActionOptions {
+            priority: 7,
+            ..Default::default()
+        };
+        client.set_action_options(REBOOT, options);
+        client.enable_device_management();
+        assert_eq!(priority(&client, REBOOT), Some(7));
+    }
+
+    #[test]
+    fn reboot_completes_before_restarting() {
+        let resets = Arc::new(Mutex::new(Vec::new()));
+        let builder = ByteBeamClient::builder().reset_hook({
+            let resets = resets.clone();
+            move |reason| resets.lock().unwrap().push(reason.clone())
+        });
+        let (_client, broker) = connect(builder);
+
+        let (states, _) = run(&broker, REBOOT, "");
+        assert_eq!(states, ["Completed"]);
+        wait_for(|| !resets.lock().unwrap().is_empty());
+        assert_eq!(*resets.lock().unwrap(), [ResetReason::Restart("1".into())]);
+    }
+
+    #[test]
+    fn ping_echoes_payload() {
+        let (_client, broker) = connect(ByteBeamClient::builder());
+        let (states, responses) = run(&broker, PING, r#"{"nonce":42}"#);
+        assert_eq!(states, ["Completed"]);
+        assert_eq!(responses, [json!({ "nonce": 42 })]);
+    }
+
+    #[test]
+    fn set_config_stores_settings() {
+        let path = std::env::temp_dir().join(format!("settings_{}", std::process::id()));
+        std::fs::remove_file(&path).ok();
+        let builder = ByteBeamClient::builder().settings_store(store::File::new(&path));
+        let (client, broker) = connect(builder);
+
+        let (states, _) = run(&broker, SET_CONFIG, r#"{"interval":5,"unit":"s"}"#);
+        assert_eq!(states, ["Completed"]);
+        assert_eq!(client.setting::<u32>("interval").unwrap(), Some(5));
+        assert_eq!(client.setting::<String>("unit").unwrap(), Some("s".into()));
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn set_config_fails_without_settings_store() {
+        let (_client, broker) = connect(ByteBeamClient::builder());
+        let (states, _) = run(&broker, SET_CONFIG, r#"{"interval":5}"#);
+        assert_eq!(states, ["Failed"]);
+    }
+
+    #[test]
+    fn device_info_reports_what_host_knows() {
+        let (_client, broker) = connect(ByteBeamClient::builder().firmware_version("1.2.3"));
+        let (states, responses) = run(&broker, DEVICE_INFO, "");
+        assert_eq!(states, ["Completed"]);
+        assert_eq!(
+            responses,
+            [json!({
+                "sdk_version": env!("CARGO_PKG_VERSION"),
+                "firmware_version": "1.2.3",
+            })]
+        );
+    }
+}
@@ -0,0 +1,95 @@
+//! Messages exchanged with Bytebeam cloud and the topics they are sent on
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{action::CancellationToken, error::Result, ByteBeamError};
+
+/// Actions sent by Bytebeam cloud
+#[derive(Deserialize)]
+pub struct Action {
+    pub id: String,
+    pub kind: String,
+    pub name: String,
+    pub payload: Option<String>,
+    /// Set if cloud asks to cancel this action
+    #[serde(skip)]
+    pub cancellation: CancellationToken,
+}
+
+/// Payload of action cancelling another action
+#[derive(Deserialize)]
+pub(crate) struct CancelAction {
+    pub action_id: String,
+    pub name: String,
+}
+
+#[derive(Serialize)]
+pub(crate) struct ActionStatus<'a> {
+    pub id: &'a str,
+    pub timestamp: u64,
+    pub errors: &'a [&'a str],
+    pub progress: u32,
+    pub state: &'a str,
+}
+
+#[derive(Serialize)]
+pub(crate) struct StreamPayload<'a, T>
+where
+    T: Serialize,
+{
+    pub id: &'a str,
+    pub sequence: u32,
+    pub timestamp: u64,
+    #[serde(flatten)]
+    pub payload: T,
+}
+
+/// Topic on which cloud sends actions to device
+pub fn actions_topic(project_id: &str, device_id: &str) -> String {
+    format!("/tenants/{project_id}/devices/{device_id}/actions")
+}
+
+/// Topic on which device reports progress of actions
+pub fn action_status_topic(project_id: &str, device_id: &str) -> String {
+    format!("/tenants/{project_id}/devices/{device_id}/action/status")
+}
+
+/// Topic on which device publishes data of `stream_name`
+pub fn stream_topic(project_id: &str, device_id: &str, stream_name: &str) -> String {
+    format!("/tenants/{project_id}/devices/{device_id}/events/{stream_name}/jsonarray")
+}
+
+/// Milliseconds since UNIX epoch, as expected in `timestamp` fields
+///
+/// Make sure system time is synchronized ( e.g. using SNTP ) before publishing anything
+pub(crate) fn timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// JSON array of records of a stream, as published on [`stream_topic`]
+///
+/// Records get consecutive sequence numbers starting at `sequence`, wrapping around at
+/// `u32::MAX`, and the same timestamp.
+pub(crate) fn stream_records<P: Serialize>(
+    device_id: &str,
+    sequence: u32,
+    payloads: impl IntoIterator<Item = P>,
+) -> Result<Vec<u8>> {
+    let timestamp = timestamp();
+    let stream_payload: Vec<_> = payloads
+        .into_iter()
+        .enumerate()
+        .map(|(i, payload)| StreamPayload {
+            id: device_id,
+            sequence: sequence.wrapping_add(i as u32),
+            timestamp,
+            payload,
+        })
+        .collect();
+
+    serde_json::to_vec(&stream_payload).map_err(ByteBeamError::Serialization)
+}
@@ -0,0 +1,531 @@
+//! Store-and-forward queue for stream data published while client is offline
+use std::{
+    collections::VecDeque,
+    fs::{self, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::PathBuf,
+};
+
+use log::{error, warn};
+
+use crate::error::Result;
+
+/// Which entry to give up on when offline queue or action queue is full
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DropPolicy {
+    /// Drop oldest queued entry to make room for new one
+    Oldest,
+    /// Keep queued entries, drop new one
+    Newest,
+}
+
+/// File on an already mounted filesystem ( SPIFFS, LittleFS, SD card, ... ) to which records are
+/// spilled once RAM buffer is full
+///
+/// Records in file survive a reboot, and are sent once client connects again.
+#[derive(Clone, Debug)]
+pub struct Spill {
+    /// Path of spill file, created if it doesn't exist
+    pub path: PathBuf,
+    /// Upper bound of queued data in file, in bytes
+    ///
+    /// Records stay in file till they are published, also while they wait in RAM to be.
+    pub max_bytes: u64,
+}
+
+/// Limits of queue buffering stream data while client is offline
+///
+/// # Example
+/// ```no_run
+/// use bytebeam_esp_rs::{ByteBeamClient, DropPolicy, OfflineQueueOptions, Spill};
+///
+/// let bytebeam_client = ByteBeamClient::builder()
+///     .offline_queue(OfflineQueueOptions {
+///         ram_capacity: 32,
+///         spill: Some(Spill {
+///             path: "/spiffs/bytebeam_queue".into(),
+///             max_bytes: 512 * 1024,
+///         }),
+///         drop_policy: DropPolicy::Oldest,
+///     })
+///     .connect()?;
+/// # anyhow::Ok(())
+/// ```
+#[derive(Clone, Debug)]
+pub struct OfflineQueueOptions {
+    /// Number of records kept in RAM
+    pub ram_capacity: usize,
+    /// Where records go once RAM is full, dropped according to `drop_policy` if `None`
+    pub spill: Option<Spill>,
+    /// What to do once both RAM and spill file are full
+    pub drop_policy: DropPolicy,
+}
+
+impl Default for OfflineQueueOptions {
+    fn default() -> Self {
+        OfflineQueueOptions {
+            ram_capacity: 64,
+            spill: None,
+            drop_policy: DropPolicy::Oldest,
+        }
+    }
+}
+
+/// A serialized message waiting to be published
+pub(crate) struct Record {
+    pub topic: String,
+    pub payload: Vec<u8>,
+}
+
+impl Record {
+    fn encoded_len(&self) -> u64 {
+        (8 + self.topic.len() + self.payload.len()) as u64
+    }
+}
+
+/// Records in order of publishing, oldest ones in RAM and rest in spill file
+///
+/// Spill file is only used once RAM is full, so as long as it has records that aren't loaded RAM
+/// is full too. Records loaded from spill file stay in it till they are published, so that they
+/// aren't lost on a reboot.
+pub(crate) struct OfflineQueue {
+    ram: VecDeque<Entry>,
+    ram_capacity: usize,
+    spill: Option<SpillFile>,
+    drop_policy: DropPolicy,
+}
+
+/// Record in RAM, which might also be in spill file
+struct Entry {
+    record: Record,
+    spilled: bool,
+}
+
+impl OfflineQueue {
+    pub fn open(options: &OfflineQueueOptions) -> Result<Self> {
+        let mut queue = OfflineQueue {
+            ram: VecDeque::with_capacity(options.ram_capacity),
+            ram_capacity: options.ram_capacity.max(1),
+            spill: options.spill.as_ref().map(SpillFile::open).transpose()?,
+            drop_policy: options.drop_policy,
+        };
+        // records left over from before reboot
+        queue.refill();
+
+        Ok(queue)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ram.is_empty()
+    }
+
+    /// Oldest record in queue
+    pub fn front(&self) -> Option<&Record> {
+        self.ram.front().map(|entry| &entry.record)
+    }
+
+    /// Remove oldest record from queue, once it is published
+    pub fn pop(&mut self) {
+        self.drop_front();
+        self.refill();
+    }
+
+    /// Add `record` to the end of queue, dropping records if queue is full
+    ///
+    /// Returns number of records dropped, which includes `record` itself if it wasn't queued.
+    #[must_use]
+    pub fn push(&mut self, record: Record) -> usize {
+        let spill = match &self.spill {
+            Some(spill) if self.ram.len() >= self.ram_capacity => spill,
+            _ => {
+                let mut dropped = 0;
+                if self.ram.len() >= self.ram_capacity {
+                    if self.drop_policy == DropPolicy::Newest {
+                        warn!("Offline queue is full, dropping newest record");
+                        return 1;
+                    }
+                    warn!("Offline queue is full, dropping oldest record");
+                    self.drop_front();
+                    dropped += 1;
+                }
+                self.ram.push_back(Entry {
+                    record,
+                    spilled: false,
+                });
+                return dropped;
+            }
+        };
+
+        // would otherwise wipe out whole backlog without fitting anyway
+        if record.encoded_len() > spill.max_bytes {
+            warn!("Record is larger than offline queue file, dropping it");
+            return 1;
+        }
+
+        let mut dropped = 0;
+        while self
+            .spill
+            .as_ref()
+            .is_some_and(|spill| !spill.fits(&record))
+        {
+            if self.drop_policy == DropPolicy::Newest || self.ram.is_empty() {
+                warn!("Offline queue is full, dropping newest record");
+                return dropped + 1;
+            }
+            warn!("Offline queue is full, dropping oldest record");
+            self.drop_front();
+            self.refill();
+            dropped += 1;
+        }
+
+        if let Some(spill) = &mut self.spill {
+            if let Err(e) = spill.push(&record) {
+                error!("Failed to write offline queue file, dropping record: {e}");
+                dropped += 1;
+            }
+        }
+
+        dropped
+    }
+
+    /// Remove oldest record from RAM and spill file
+    fn drop_front(&mut self) {
+        let Some(entry) = self.ram.pop_front() else {
+            return;
+        };
+        if let (true, Some(spill)) = (entry.spilled, &mut self.spill) {
+            if let Err(e) = spill.consume(entry.record.encoded_len()) {
+                error!("Failed to update offline queue file: {e}");
+            }
+        }
+    }
+
+    /// Load records from spill file to RAM while there is space
+    fn refill(&mut self) {
+        let Some(spill) = &mut self.spill else {
+            return;
+        };
+
+        while self.ram.len() < self.ram_capacity {
+            match spill.load() {
+                Ok(Some(record)) => self.ram.push_back(Entry {
+                    record,
+                    spilled: true,
+                }),
+                Ok(None) => break,
+                Err(e) => {
+                    // rest of file can't be framed without a readable record
+                    error!("Failed to read offline queue file, dropping unread records: {e}");
+                    if let Err(e) = spill.truncate() {
+                        error!("Failed to truncate offline queue file: {e}");
+                    }
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Length of header holding offset of oldest record in file
+const HEADER_LEN: u64 = 8;
+
+/// Append only file of `[topic length][topic][payload length][payload]` records
+///
+/// Records are loaded one by one from the offset stored in header, which only moves past them once
+/// they are published. File is truncated once all of them are published, or compacted when it runs
+/// out of space.
+struct SpillFile {
+    file: fs::File,
+    /// Offset of oldest record which isn't published yet
+    read_offset: u64,
+    /// Offset of oldest record which isn't loaded to RAM yet
+    load_offset: u64,
+    len: u64,
+    max_bytes: u64,
+}
+
+impl SpillFile {
+    fn open(spill: &Spill) -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&spill.path)?;
+        let len = file.metadata()?.len();
+
+        let mut spill_file = SpillFile {
+            file,
+            read_offset: HEADER_LEN,
+            load_offset: HEADER_LEN,
+            len: HEADER_LEN,
+            max_bytes: spill.max_bytes,
+        };
+
+        if len < HEADER_LEN {
+            spill_file.reset()?;
+        } else {
+            let mut header = [0; HEADER_LEN as usize];
+            spill_file.file.read_exact(&mut header)?;
+            spill_file.read_offset = u64::from_le_bytes(header).clamp(HEADER_LEN, len);
+            spill_file.load_offset = spill_file.read_offset;
+            spill_file.len = len;
+        }
+
+        Ok(spill_file)
+    }
+
+    fn fits(&self, record: &Record) -> bool {
+        self.len - self.read_offset + record.encoded_len() <= self.max_bytes
+    }
+
+    fn push(&mut self, record: &Record) -> io::Result<()> {
+        if self.len - HEADER_LEN + record.encoded_len() > self.max_bytes {
+            self.compact()?;
+        }
+
+        self.file.seek(SeekFrom::Start(self.len))?;
+        for field in [record.topic.as_bytes(), &record.payload] {
+            self.file.write_all(&(field.len() as u32).to_le_bytes())?;
+            self.file.write_all(field)?;
+        }
+        self.file.flush()?;
+        self.len += record.encoded_len();
+
+        Ok(())
+    }
+
+    /// Read next record which isn't loaded yet, leaving it in file till it is consumed
+    fn load(&mut self) -> io::Result<Option<Record>> {
+        if self.load_offset >= self.len {
+            return Ok(None);
+        }
+
+        self.file.seek(SeekFrom::Start(self.load_offset))?;
+        let topic = String::from_utf8(self.read_field()?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let payload = self.read_field()?;
+        let record = Record { topic, payload };
+        self.load_offset += record.encoded_len();
+
+        Ok(Some(record))
+    }
+
+    /// Read a length prefixed field at current position of file
+    fn read_field(&mut self) -> io::Result<Vec<u8>> {
+        let mut len = [0; 4];
+        self.file.read_exact(&mut len)?;
+        let len = u32::from_le_bytes(len) as u64;
+        // a corrupted length could otherwise ask for gigabytes
+        let remaining = self.len - self.file.stream_position()?;
+        if len > remaining {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("field of {len} bytes with only {remaining} bytes left in file"),
+            ));
+        }
+
+        let mut field = vec![0; len as usize];
+        self.file.read_exact(&mut field)?;
+
+        Ok(field)
+    }
+
+    /// Forget oldest loaded record, of `len` bytes, as it is published or dropped
+    fn consume(&mut self, len: u64) -> io::Result<()> {
+        self.read_offset = (self.read_offset + len).min(self.load_offset);
+        if self.read_offset >= self.len {
+            self.reset()
+        } else {
+            self.write_header()
+        }
+    }
+
+    /// Drop records which aren't loaded yet
+    fn truncate(&mut self) -> io::Result<()> {
+        self.len = self.load_offset;
+        self.file.set_len(self.len)?;
+        if self.read_offset >= self.len {
+            self.reset()?;
+        }
+
+        Ok(())
+    }
+
+    /// Move unpublished records to start of file
+    fn compact(&mut self) -> io::Result<()> {
+        let mut unread = Vec::with_capacity((self.len - self.read_offset) as usize);
+        self.file.seek(SeekFrom::Start(self.read_offset))?;
+        self.file.read_to_end(&mut unread)?;
+
+        self.file.seek(SeekFrom::Start(HEADER_LEN))?;
+        self.file.write_all(&unread)?;
+        self.len = HEADER_LEN + unread.len() as u64;
+        self.file.set_len(self.len)?;
+        self.load_offset -= self.read_offset - HEADER_LEN;
+        self.read_offset = HEADER_LEN;
+        self.write_header()
+    }
+
+    /// Drop all records
+    fn reset(&mut self) -> io::Result<()> {
+        self.file.set_len(HEADER_LEN)?;
+        self.len = HEADER_LEN;
+        self.read_offset = HEADER_LEN;
+        self.load_offset = HEADER_LEN;
+        self.write_header()
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(&self.read_offset.to_le_bytes())?;
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(n: usize) -> Record {
+        Record {
+            topic: "/tenants/p/devices/d/events/s/jsonarray".into(),
+            payload: format!("[{{\"sequence\":{n}}}]").into_bytes(),
+        }
+    }
+
+    fn drain(queue: &mut OfflineQueue) -> Vec<Vec<u8>> {
+        let mut payloads = vec![];
+        while let Some(record) = queue.front() {
+            payloads.push(record.payload.clone());
+            queue.pop();
+        }
+        payloads
+    }
+
+    /// Push `records`, returning how many records were dropped
+    fn push(queue: &mut OfflineQueue, records: impl IntoIterator<Item = usize>) -> usize {
+        records.into_iter().map(|n| queue.push(record(n))).sum()
+    }
+
+    fn payloads(records: impl IntoIterator<Item = usize>) -> Vec<Vec<u8>> {
+        records.into_iter().map(|n| record(n).payload).collect()
+    }
+
+    fn options(
+        name: &str,
+        ram_capacity: usize,
+        records: u64,
+        drop_policy: DropPolicy,
+    ) -> OfflineQueueOptions {
+        let path =
+            std::env::temp_dir().join(format!("offline_queue_{}_{name}", std::process::id()));
+        fs::remove_file(&path).ok();
+        OfflineQueueOptions {
+            ram_capacity,
+            spill: Some(Spill {
+                path,
+                max_bytes: records * record(0).encoded_len(),
+            }),
+            drop_policy,
+        }
+    }
+
+    #[test]
+    fn ram_only_drops_by_policy() {
+        for (drop_policy, kept) in [
+            (DropPolicy::Oldest, [2, 3, 4]),
+            (DropPolicy::Newest, [0, 1, 2]),
+        ] {
+            let mut queue = OfflineQueue::open(&OfflineQueueOptions {
+                ram_capacity: 3,
+                spill: None,
+                drop_policy,
+            })
+            .unwrap();
+            assert_eq!(push(&mut queue, 0..5), 2);
+            assert_eq!(drain(&mut queue), payloads(kept));
+        }
+    }
+
+    #[test]
+    fn spills_in_order() {
+        let options = options("order", 2, 10, DropPolicy::Oldest);
+        let mut queue = OfflineQueue::open(&options).unwrap();
+        assert_eq!(push(&mut queue, 0..8), 0);
+        assert_eq!(drain(&mut queue), payloads(0..8));
+        assert!(queue.is_empty());
+        assert_eq!(
+            fs::metadata(&options.spill.unwrap().path).unwrap().len(),
+            HEADER_LEN
+        );
+    }
+
+    #[test]
+    fn spilled_records_survive_reopen_till_published() {
+        let options = options("reopen", 2, 10, DropPolicy::Oldest);
+        let mut queue = OfflineQueue::open(&options).unwrap();
+        assert_eq!(push(&mut queue, 0..6), 0);
+        // publishes record 0, loads record 2 from file into RAM
+        queue.pop();
+        drop(queue);
+
+        // record 1 was only ever in RAM
+        let mut queue = OfflineQueue::open(&options).unwrap();
+        assert_eq!(drain(&mut queue), payloads(2..6));
+
+        let mut queue = OfflineQueue::open(&options).unwrap();
+        assert!(queue.is_empty());
+        assert_eq!(push(&mut queue, [6]), 0);
+        assert_eq!(drain(&mut queue), payloads([6]));
+    }
+
+    #[test]
+    fn full_spill_drops_by_policy() {
+        let mut queue = OfflineQueue::open(&options("oldest", 2, 3, DropPolicy::Oldest)).unwrap();
+        assert_eq!(push(&mut queue, 0..8), 5);
+        // records loaded into RAM stay in file, so file bounds what's kept
+        assert_eq!(drain(&mut queue), payloads(5..8));
+
+        let mut queue = OfflineQueue::open(&options("newest", 2, 3, DropPolicy::Newest)).unwrap();
+        assert_eq!(push(&mut queue, 0..8), 3);
+        assert_eq!(drain(&mut queue), payloads(0..5));
+    }
+
+    #[test]
+    fn oversized_record_keeps_backlog() {
+        let mut queue =
+            OfflineQueue::open(&options("oversized", 1, 3, DropPolicy::Oldest)).unwrap();
+        assert_eq!(push(&mut queue, 0..4), 0);
+        let large = Record {
+            topic: "large".into(),
+            payload: vec![b'x'; 1024],
+        };
+        assert_eq!(queue.push(large), 1);
+        // makes room by dropping records 0 and 1
+        assert_eq!(push(&mut queue, [4]), 2);
+        assert_eq!(drain(&mut queue), payloads(2..5));
+    }
+
+    #[test]
+    fn corrupted_file_is_dropped_from_bad_record() {
+        let options = options("corrupted", 1, 10, DropPolicy::Oldest);
+        let mut queue = OfflineQueue::open(&options).unwrap();
+        assert_eq!(push(&mut queue, 0..4), 0);
+        drop(queue);
+
+        // length of topic of record 2 claims more than what's left in file
+        let path = &options.spill.as_ref().unwrap().path;
+        let mut file = OpenOptions::new().write(true).open(path).unwrap();
+        file.seek(SeekFrom::Start(HEADER_LEN + record(0).encoded_len()))
+            .unwrap();
+        file.write_all(&u32::MAX.to_le_bytes()).unwrap();
+        drop(file);
+
+        let mut queue = OfflineQueue::open(&options).unwrap();
+        assert_eq!(drain(&mut queue), payloads([1]));
+
+        assert_eq!(push(&mut queue, [4, 5]), 0);
+        assert_eq!(drain(&mut queue), payloads([4, 5]));
+    }
+}
@@ -0,0 +1,169 @@
+//! Sequence numbers of stream records, tracked per stream by client
+//!
+//! Every stream gets its own monotonically increasing sequence, starting from `1`. To keep it
+//! increasing across reboots, sequences can be persisted in a [`BlobStore`]. Store isn't
+//! written for every record, instead a block of sequence numbers is reserved at once and a reboot
+//! skips whatever was left of it.
+//!
+//! # Example
+//! ```no_run
+//! use bytebeam_esp_rs::{store, ByteBeamClient};
+//!
+//! let bytebeam_client = ByteBeamClient::builder()
+//!     .sequence_store(store::File::new("/littlefs/sequences.json"))
+//!     .connect()?;
+//!
+//! bytebeam_client.publish("heartbeat", serde_json::json!({ "status": "ok" }))?;
+//! # anyhow::Ok(())
+//! ```
+use std::{collections::BTreeMap, sync::Mutex};
+
+use log::warn;
+
+use crate::store::{BlobStore, JsonMap};
+
+/// Number of sequences reserved with a single write to store
+const BLOCK: u32 = 100;
+
+struct StreamSequence {
+    /// Last sequence handed out
+    last: u32,
+    /// Highest sequence recorded in store
+    reserved: u32,
+}
+
+/// Sequences of all streams published by a client
+pub(crate) struct SequenceTracker {
+    streams: Mutex<BTreeMap<String, StreamSequence>>,
+    /// Highest sequence reserved for each stream
+    store: Option<Mutex<JsonMap<u32>>>,
+}
+
+impl SequenceTracker {
+    pub fn new(store: Option<Box<dyn BlobStore>>) -> Self {
+        SequenceTracker {
+            streams: Mutex::new(BTreeMap::new()),
+            store: store.map(|store| Mutex::new(JsonMap::new("sequences", store))),
+        }
+    }
+
+    /// Reserve `count` consecutive sequences of `stream`, returning the first one
+    ///
+    /// Panics if `count` is `0`, as there would be no sequence to return.
+    pub fn next(&self, stream: &str, count: u32) -> u32 {
+        assert!(count > 0, "reserving no sequences of {stream}");
+        let mut streams = self.streams.lock().unwrap();
+        let sequence = streams.entry(stream.into()).or_insert_with(|| {
+            let reserved = self.load(stream);
+            StreamSequence {
+                last: reserved,
+                reserved,
+            }
+        });
+
+        let first = sequence.last.wrapping_add(1);
+        sequence.last = sequence.last.wrapping_add(count);
+
+        if sequence.last > sequence.reserved || sequence.last < first {
+            sequence.reserved = sequence.last.saturating_add(BLOCK);
+            self.store(stream, sequence.reserved);
+        }
+
+        first
+    }
+
+    fn load(&self, stream: &str) -> u32 {
+        let Some(store) = &self.store else {
+            return 0;
+        };
+
+        match store.lock().unwrap().get(stream) {
+            Ok(reserved) => reserved.unwrap_or(0),
+            Err(e) => {
+                warn!("Failed to load sequence of {stream}, starting over: {e}");
+                0
+            }
+        }
+    }
+
+    fn store(&self, stream: &str, reserved: u32) {
+        let Some(store) = &self.store else {
+            return;
+        };
+
+        if let Err(e) = store.lock().unwrap().insert(stream, reserved) {
+            warn!("Failed to store sequence of {stream}: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use super::*;
+    use crate::store::{BlobStore, File};
+
+    fn path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("sequences_{}_{name}", std::process::id()));
+        fs::remove_file(&path).ok();
+        path
+    }
+
+    fn open(path: &PathBuf) -> SequenceTracker {
+        SequenceTracker::new(Some(Box::new(File::new(path))))
+    }
+
+    #[test]
+    fn counts_each_stream_separately() {
+        let tracker = SequenceTracker::new(None);
+        assert_eq!(tracker.next("a", 1), 1);
+        assert_eq!(tracker.next("a", 1), 2);
+        assert_eq!(tracker.next("b", 1), 1);
+        assert_eq!(tracker.next("a", 5), 3);
+        assert_eq!(tracker.next("a", 1), 8);
+        assert_eq!(tracker.next("b", 1), 2);
+    }
+
+    #[test]
+    fn skips_reserved_block_after_reset() {
+        let path = path("reset");
+        let tracker = open(&path);
+        assert_eq!(tracker.next("a", 1), 1);
+        assert_eq!(tracker.next("a", 1), 2);
+        assert_eq!(tracker.next("b", 1), 1);
+        drop(tracker);
+
+        // sequences up to 101 were reserved with the first record of each stream
+        let tracker = open(&path);
+        assert_eq!(tracker.next("a", 1), 102);
+        assert_eq!(tracker.next("b", 1), 102);
+        assert_eq!(tracker.next("c", 1), 1);
+
+        let tracker = open(&path);
+        assert_eq!(tracker.next("a", 1), 203);
+    }
+
+    #[test]
+    fn wraps_around_at_max() {
+        let path = path("wrap");
+        File::new(&path)
+            .store(format!(r#"{{"a": {}}}"#, u32::MAX - 5).as_bytes())
+            .unwrap();
+
+        let tracker = open(&path);
+        assert_eq!(tracker.next("a", 1), u32::MAX - 4);
+        assert_eq!(tracker.next("a", 10), u32::MAX - 3);
+        assert_eq!(tracker.next("a", 1), 6);
+        drop(tracker);
+
+        // block was reserved again from wrapped sequence
+        assert_eq!(open(&path).next("a", 1), 106);
+    }
+
+    #[test]
+    #[should_panic(expected = "reserving no sequences")]
+    fn rejects_empty_reservation() {
+        SequenceTracker::new(None).next("a", 0);
+    }
+}
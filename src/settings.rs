@@ -0,0 +1,51 @@
+//! Settings of device, which cloud can update with the `set_config` action
+//!
+//! Settings are JSON values stored by key, all together as a JSON object in the
+//! [`BlobStore`](crate::store::BlobStore) set with
+//! [`ByteBeamClientBuilder::settings_store`](crate::ByteBeamClientBuilder::settings_store).
+//! Action is handled once [`ByteBeamClient::enable_device_management`] is called, with its
+//! payload being an object of settings to update.
+//!
+//! # Example
+//! ```no_run
+//! use bytebeam_esp_rs::{store, ByteBeamClient};
+//!
+//! let bytebeam_client = ByteBeamClient::builder()
+//!     .settings_store(store::File::new("/littlefs/settings.json"))
+//!     .connect()?;
+//! bytebeam_client.enable_device_management();
+//!
+//! let interval: u64 = bytebeam_client.setting("publish_interval")?.unwrap_or(10);
+//! # anyhow::Ok(())
+//! ```
+use std::sync::Mutex;
+
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+use crate::{error::Result, store::JsonMap, transport::Transport, ByteBeamClient, ByteBeamError};
+
+impl<T: Transport> ByteBeamClient<T> {
+    /// Value of setting `key`, `None` if it was never set
+    ///
+    /// Fails if there is no settings store, or if value doesn't match `V`.
+    pub fn setting<V: DeserializeOwned>(&self, key: &str) -> Result<Option<V>> {
+        let value = self.settings_store()?.lock().unwrap().get(key)?;
+        value
+            .map(serde_json::from_value)
+            .transpose()
+            .map_err(ByteBeamError::Serialization)
+    }
+
+    /// Replace value of setting `key` with `value`
+    pub fn set_setting(&self, key: &str, value: impl Serialize) -> Result<()> {
+        let value = serde_json::to_value(value).map_err(ByteBeamError::Serialization)?;
+        self.settings_store()?.lock().unwrap().insert(key, value)
+    }
+
+    fn settings_store(&self) -> Result<&Mutex<JsonMap<Value>>> {
+        self.settings
+            .as_ref()
+            .ok_or_else(|| ByteBeamError::Storage("no settings store configured".into()))
+    }
+}
@@ -0,0 +1,274 @@
+//! Diagnostic commands cloud can run on device with the `run_command` action
+//!
+//! Payload of action names a command registered with [`ByteBeamClient::register_command`] and
+//! its arguments, e.g. `{"command": "log_level", "args": ["debug"]}`. Output of command is
+//! published to `action_responses` stream, see [`ActionContext::respond`]. Built-in commands are:
+//!
+//! - `help`: names of registered commands
+//! - `log_level <off|error|warn|info|debug|trace>`: change level of logs
+//! - `heap`: free and minimum free heap, ESP-IDF only
+//! - `tasks`: name, state, priority and minimum free stack of every FreeRTOS task, ESP-IDF only,
+//!   needs `CONFIG_FREERTOS_USE_TRACE_FACILITY`
+//! - `rssi`: signal strength of Wi-Fi access point, ESP-IDF only
+//! - `nvs [namespace]`: usage of entries of default NVS partition and its keys, with their types,
+//!   ESP-IDF only. Values aren't dumped, as NVS usually holds credentials
+//!
+//! # Example
+//! ```no_run
+//! use bytebeam_esp_rs::ByteBeamClient;
+//!
+//! let bytebeam_client = ByteBeamClient::builder().connect()?;
+//! bytebeam_client.enable_remote_shell();
+//! bytebeam_client.register_command("sensors", |_args| Ok("temperature: ok".into()));
+//! # anyhow::Ok(())
+//! ```
+use std::{str::FromStr, sync::Arc};
+
+use log::{info, LevelFilter};
+use serde::{Deserialize, Serialize};
+
+use crate::{error::Result, transport::Transport, ActionContext, ByteBeamClient, ByteBeamError};
+
+/// Name of action which runs commands
+pub const RUN_COMMAND: &str = "run_command";
+
+/// Diagnostic command, taking arguments and returning its output
+pub(crate) type Command = Arc<dyn Fn(&[String]) -> Result<String> + Send + Sync>;
+
+impl<T: Transport> ByteBeamClient<T> {
+    /// Handle [`RUN_COMMAND`] action, registering built-in commands
+    ///
+    /// Commands registered earlier with the same names as built-in ones are replaced.
+    pub fn enable_remote_shell(&self) {
+        self.register_command("log_level", log_level);
+        #[cfg(feature = "esp-idf")]
+        {
+            self.register_command("heap", esp::heap);
+            self.register_command("tasks", esp::tasks);
+            self.register_command("rssi", esp::rssi);
+            self.register_command("nvs", esp::nvs);
+        }
+
+        self.register_typed_action(RUN_COMMAND.into(), run_command);
+    }
+
+    /// Register `command` under `name`, replacing command registered earlier for it, if any
+    ///
+    /// Commands run on action workers, so they should be quick.
+    pub fn register_command(
+        &self,
+        name: &str,
+        command: impl Fn(&[String]) -> Result<String> + Send + Sync + 'static,
+    ) {
+        info!("setting command {name}");
+        self.commands
+            .lock()
+            .unwrap()
+            .insert(name.into(), Arc::new(command));
+    }
+}
+
+#[derive(Deserialize)]
+struct RunCommand {
+    command: String,
+    #[serde(default)]
+    args: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct CommandOutput<'a> {
+    command: &'a str,
+    output: String,
+}
+
+fn run_command<T: Transport>(run: RunCommand, ctx: ActionContext<T>) {
+    info!("Running command {} {:?}", run.command, run.args);
+    let result = if run.command == "help" {
+        let commands = ctx.client().commands.lock().unwrap();
+        let mut names: Vec<_> = commands.keys().map(String::as_str).collect();
+        names.push("help");
+        Ok(names.join("\n"))
+    } else {
+        // not holding on to map while command runs
+        let command = ctx
+            .client()
+            .commands
+            .lock()
+            .unwrap()
+            .get(&run.command)
+            .cloned();
+        match command {
+            Some(command) => command(&run.args),
+            None => Err(ByteBeamError::UnknownCommand(run.command.clone())),
+        }
+    };
+
+    let result = result.and_then(|output| {
+        ctx.respond(CommandOutput {
+            command: &run.command,
+            output,
+        })
+    });
+    match result {
+        Ok(_) => ctx.complete().ok(),
+        Err(e) => ctx.fail(&[&e.to_string()]).ok(),
+    };
+}
+
+fn log_level(args: &[String]) -> Result<String> {
+    let [level] = args else {
+        return Ok(format!("log level is {}", log::max_level()));
+    };
+    let level = LevelFilter::from_str(level).map_err(|_| {
+        ByteBeamError::InvalidArgument(format!(
+            "{level} isn't a log level, expected one of off, error, warn, info, debug, trace"
+        ))
+    })?;
+
+    log::set_max_level(level);
+    #[cfg(feature = "esp-idf")]
+    crate::esp::system::set_log_level(level);
+
+    Ok(format!("log level set to {level}"))
+}
+
+#[cfg(feature = "esp-idf")]
+mod esp {
+    use crate::{error::Result, esp::system};
+
+    pub fn heap(_args: &[String]) -> Result<String> {
+        Ok(format!(
+            "free: {} bytes\nminimum free: {} bytes",
+            system::free_heap(),
+            system::min_free_heap()
+        ))
+    }
+
+    pub fn tasks(_args: &[String]) -> Result<String> {
+        let tasks = system::tasks();
+        let mut output = format!("tasks: {}", tasks.len());
+        for task in tasks {
+            output += &format!(
+                "\n{:<16} {:<9} priority: {:>2} min free stack: {} bytes",
+                task.name, task.state, task.priority, task.min_free_stack
+            );
+        }
+        Ok(output)
+    }
+
+    pub fn rssi(_args: &[String]) -> Result<String> {
+        Ok(format!("rssi: {} dBm", system::wifi_rssi()?))
+    }
+
+    pub fn nvs(args: &[String]) -> Result<String> {
+        let stats = system::nvs_stats()?;
+        let mut output = format!(
+            "used entries: {}\nfree entries: {}\ntotal entries: {}\nnamespaces: {}",
+            stats.used_entries, stats.free_entries, stats.total_entries, stats.namespace_count
+        );
+        for entry in system::nvs_entries(args.first().map(String::as_str))? {
+            output += &format!("\n{}/{}: {}", entry.namespace, entry.key, entry.kind);
+        }
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::transport::{
+        testing::{
+            action_states, device_config, send_action, stream_messages, wait_for,
+            ACTION_STATUS_TOPIC,
+        },
+        MockBroker, MockTransport,
+    };
+
+    fn connect() -> (Arc<ByteBeamClient<MockTransport>>, MockBroker) {
+        let (transport, events, broker) = MockTransport::new();
+        let client = ByteBeamClient::builder()
+            .device_config(device_config())
+            .connect_with(transport, events)
+            .unwrap();
+        client.enable_remote_shell();
+        broker.connect();
+
+        (client, broker)
+    }
+
+    /// Run `command` as action `id`, returning its final state and output, if any
+    fn run(broker: &MockBroker, id: &str, command: Value) -> (String, Option<String>) {
+        send_action(broker, id, RUN_COMMAND, &command.to_string());
+        wait_for(|| !action_states(broker, id).is_empty());
+
+        let output = stream_messages(broker, "action_responses")
+            .into_iter()
+            .flatten()
+            .find(|response| response["action_id"] == id)
+            .map(|response| response["response"]["output"].as_str().unwrap().to_owned());
+        (action_states(broker, id).remove(0), output)
+    }
+
+    /// Errors reported with failure of action `id`
+    fn errors(broker: &MockBroker, id: &str) -> Vec<Value> {
+        broker
+            .published()
+            .into_iter()
+            .filter(|message| message.topic == ACTION_STATUS_TOPIC)
+            .flat_map(|message| serde_json::from_slice::<Vec<Value>>(&message.payload).unwrap())
+            .filter(|status| status["id"] == id)
+            .flat_map(|status| status["errors"].as_array().unwrap().clone())
+            .collect()
+    }
+
+    #[test]
+    fn help_lists_commands() {
+        let (client, broker) = connect();
+        client.register_command("sensors", |_| Ok("ok".into()));
+
+        let (state, output) = run(&broker, "1", json!({ "command": "help" }));
+        assert_eq!(state, "Completed");
+        assert_eq!(output.unwrap(), "log_level\nsensors\nhelp");
+    }
+
+    #[test]
+    fn log_level_sets_valid_levels_only() {
+        let (_client, broker) = connect();
+        let initial = log::max_level();
+
+        let (state, output) = run(
+            &broker,
+            "1",
+            json!({ "command": "log_level", "args": ["debug"] }),
+        );
+        assert_eq!(state, "Completed");
+        assert_eq!(output.unwrap(), "log level set to DEBUG");
+        assert_eq!(log::max_level(), LevelFilter::Debug);
+
+        let (state, output) = run(
+            &broker,
+            "2",
+            json!({ "command": "log_level", "args": ["verbose"] }),
+        );
+        assert_eq!(state, "Failed");
+        assert_eq!(output, None);
+        assert!(errors(&broker, "2")[0]
+            .as_str()
+            .unwrap()
+            .starts_with("invalid argument: verbose isn't a log level"));
+        assert_eq!(log::max_level(), LevelFilter::Debug);
+
+        log::set_max_level(initial);
+    }
+
+    #[test]
+    fn unknown_command_fails() {
+        let (_client, broker) = connect();
+        let (state, output) = run(&broker, "1", json!({ "command": "reboot" }));
+        assert_eq!(state, "Failed");
+        assert_eq!(output, None);
+        assert_eq!(errors(&broker, "1"), ["unknown command: reboot"]);
+    }
+}
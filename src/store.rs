@@ -0,0 +1,156 @@
+//! Persistent storage of state which client keeps across reboots
+//!
+//! Such state is persisted as a single opaque blob in a [`BlobStore`], set with the matching
+//! `*_store` method of [`ByteBeamClientBuilder`](crate::ByteBeamClientBuilder). A store holds one
+//! blob, so every use needs a store of its own: a separate [`File`], or a separate key of `Nvs`
+//! on ESP-IDF.
+//!
+//! # Example
+//! ```no_run
+//! use bytebeam_esp_rs::{store, ByteBeamClient};
+//!
+//! let bytebeam_client = ByteBeamClient::builder()
+//!     .sequence_store(store::File::new("/littlefs/sequences.json"))
+//!     .connect()?;
+//! # anyhow::Ok(())
+//! ```
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use log::warn;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{error::Result, ByteBeamError};
+
+#[cfg(feature = "esp-idf")]
+pub use crate::esp::store::Nvs;
+
+/// Persistent storage of a single opaque blob
+pub trait BlobStore: Send {
+    /// Blob stored earlier, `None` if there is none
+    fn load(&mut self) -> Result<Option<Vec<u8>>>;
+
+    /// Replace stored blob with `blob`
+    fn store(&mut self, blob: &[u8]) -> Result<()>;
+}
+
+/// Blob stored in a file on an already mounted filesystem
+pub struct File {
+    path: PathBuf,
+}
+
+impl File {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        File {
+            path: path.as_ref().into(),
+        }
+    }
+}
+
+impl BlobStore for File {
+    fn load(&mut self) -> Result<Option<Vec<u8>>> {
+        match fs::read(&self.path) {
+            Ok(blob) => Ok(Some(blob)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn store(&mut self, blob: &[u8]) -> Result<()> {
+        fs::write(&self.path, blob)?;
+        Ok(())
+    }
+}
+
+/// Values stored by key as a JSON object in a [`BlobStore`], loaded on first use
+pub(crate) struct JsonMap<V> {
+    /// What values are, for logs
+    name: &'static str,
+    store: Box<dyn BlobStore>,
+    values: Option<BTreeMap<String, V>>,
+}
+
+impl<V: Clone + Serialize + DeserializeOwned> JsonMap<V> {
+    pub fn new(name: &'static str, store: Box<dyn BlobStore>) -> Self {
+        JsonMap {
+            name,
+            store,
+            values: None,
+        }
+    }
+
+    /// Value of `key`, `None` if it was never stored
+    pub fn get(&mut self, key: &str) -> Result<Option<V>> {
+        Ok(self.values()?.get(key).cloned())
+    }
+
+    /// Replace value of `key` with `value`, and write all values back to store
+    pub fn insert(&mut self, key: &str, value: V) -> Result<()> {
+        self.values()?.insert(key.into(), value);
+        let blob = serde_json::to_vec(&self.values).map_err(ByteBeamError::Serialization)?;
+        self.store.store(&blob)
+    }
+
+    fn values(&mut self) -> Result<&mut BTreeMap<String, V>> {
+        if self.values.is_none() {
+            let values = match self.store.load()? {
+                Some(blob) => serde_json::from_slice(&blob).unwrap_or_else(|e| {
+                    warn!("Ignoring corrupted {}: {e}", self.name);
+                    BTreeMap::new()
+                }),
+                None => BTreeMap::new(),
+            };
+            self.values = Some(values);
+        }
+
+        Ok(self.values.get_or_insert_with(BTreeMap::new))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(name: &str) -> File {
+        let path = std::env::temp_dir().join(format!("store_{}_{name}", std::process::id()));
+        fs::remove_file(&path).ok();
+        File::new(path)
+    }
+
+    #[test]
+    fn file_is_empty_till_stored() {
+        let mut store = file("empty");
+        assert_eq!(store.load().unwrap(), None);
+
+        store.store(b"blob").unwrap();
+        assert_eq!(store.load().unwrap(), Some(b"blob".to_vec()));
+    }
+
+    #[test]
+    fn json_map_survives_reopen() {
+        let store = file("reopen");
+        let mut map = JsonMap::new("values", Box::new(File::new(&store.path)));
+        map.insert("a", 1).unwrap();
+        map.insert("b", 2).unwrap();
+        map.insert("a", 3).unwrap();
+
+        let mut map: JsonMap<u32> = JsonMap::new("values", Box::new(store));
+        assert_eq!(map.get("a").unwrap(), Some(3));
+        assert_eq!(map.get("b").unwrap(), Some(2));
+        assert_eq!(map.get("c").unwrap(), None);
+    }
+
+    #[test]
+    fn json_map_ignores_corrupted_blob() {
+        let mut store = file("corrupted");
+        store.store(b"{\"a\":").unwrap();
+
+        let mut map = JsonMap::new("values", Box::new(store));
+        assert_eq!(map.get("a").unwrap(), None::<u32>);
+        map.insert("a", 1).unwrap();
+        assert_eq!(map.get("a").unwrap(), Some(1));
+    }
+}
@@ -0,0 +1,198 @@
+//! Typed handles of streams
+use std::{marker::PhantomData, sync::Arc};
+
+use serde::Serialize;
+
+use crate::{
+    error::Result,
+    protocol,
+    transport::{DefaultTransport, Transport},
+    ByteBeamClient,
+};
+
+/// Handle of a stream whose records are of type `P`, created with [`ByteBeamClient::stream`]
+///
+/// Topic is formatted once, and sequence numbers come from the ones client tracks for stream, so
+/// handles can be created and cloned freely.
+///
+/// # Example
+/// ```no_run
+/// use bytebeam_esp_rs::ByteBeamClient;
+/// use serde::Serialize;
+///
+/// #[derive(Serialize)]
+/// struct Temperature {
+///     celsius: f32,
+/// }
+///
+/// let bytebeam_client = ByteBeamClient::builder().connect()?;
+/// let temperature = bytebeam_client.stream::<Temperature>("temperature");
+///
+/// temperature.publish(&Temperature { celsius: 21.5 })?;
+/// # anyhow::Ok(())
+/// ```
+pub struct Stream<P, T: Transport = DefaultTransport> {
+    client: Arc<ByteBeamClient<T>>,
+    name: String,
+    topic: String,
+    _payload: PhantomData<fn(&P)>,
+}
+
+impl<T: Transport> ByteBeamClient<T> {
+    /// Typed handle of `stream_name`
+    pub fn stream<P: Serialize>(self: &Arc<Self>, stream_name: &str) -> Stream<P, T> {
+        Stream {
+            client: self.clone(),
+            name: stream_name.into(),
+            topic: protocol::stream_topic(&self.project_id, &self.device_id, stream_name),
+            _payload: PhantomData,
+        }
+    }
+}
+
+impl<P: Serialize, T: Transport> Stream<P, T> {
+    /// Name of stream
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Topic on which records of stream are published
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    /// Publish a record with next sequence number of stream
+    pub fn publish(&self, payload: &P) -> Result<u32> {
+        self.publish_batch(std::slice::from_ref(payload))
+    }
+
+    /// Publish many records in a single message
+    ///
+    /// Nothing is published for an empty `payloads`, and `0` is returned.
+    pub fn publish_batch(&self, payloads: &[P]) -> Result<u32> {
+        if payloads.is_empty() {
+            return Ok(0);
+        }
+        let sequence = self.client.next_sequence(&self.name, payloads.len() as u32);
+        let final_payload = protocol::stream_records(&self.client.device_id, sequence, payloads)?;
+
+        self.client.publish_stream_data(&self.topic, &final_payload)
+    }
+}
+
+#[cfg(feature = "schema")]
+impl<P: Serialize + schemars::JsonSchema, T: Transport> Stream<P, T> {
+    /// JSON schema of records of stream, including `id`, `sequence` and `timestamp` added by SDK
+    ///
+    /// Requires `schema` feature.
+    pub fn schema(&self) -> schemars::schema::RootSchema {
+        let mut generator = schemars::gen::SchemaGenerator::default();
+        let mut schema = generator.root_schema_for::<P>();
+        let fields = [
+            ("id", generator.subschema_for::<String>()),
+            ("sequence", generator.subschema_for::<u32>()),
+            ("timestamp", generator.subschema_for::<u64>()),
+        ];
+
+        let object = schema.schema.object();
+        for (field, field_schema) in fields {
+            object.properties.insert(field.into(), field_schema);
+            object.required.insert(field.into());
+        }
+
+        schema
+    }
+}
+
+impl<P, T: Transport> Clone for Stream<P, T> {
+    fn clone(&self) -> Self {
+        Stream {
+            client: self.client.clone(),
+            name: self.name.clone(),
+            topic: self.topic.clone(),
+            _payload: PhantomData,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+    use crate::transport::{
+        testing::{device_config, stream_messages},
+        MockBroker, MockTransport,
+    };
+
+    #[derive(Serialize)]
+    #[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+    struct Temperature {
+        celsius: u32,
+    }
+
+    fn temperature() -> (Stream<Temperature, MockTransport>, MockBroker) {
+        let (transport, events, broker) = MockTransport::new();
+        let client = ByteBeamClient::builder()
+            .device_config(device_config())
+            .connect_with(transport, events)
+            .unwrap();
+        (client.stream("temperature"), broker)
+    }
+
+    /// Field `key` of records in each message of `temperature` stream
+    fn fields(broker: &MockBroker, key: &str) -> Vec<Vec<Value>> {
+        stream_messages(broker, "temperature")
+            .into_iter()
+            .map(|records| records.iter().map(|record| record[key].clone()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn publishes_typed_records() {
+        let (temperature, broker) = temperature();
+        temperature.publish(&Temperature { celsius: 21 }).unwrap();
+        temperature.publish(&Temperature { celsius: 22 }).unwrap();
+
+        let published = broker.published();
+        assert_eq!(published[0].topic, temperature.topic());
+        assert_eq!(fields(&broker, "celsius"), [[21], [22]]);
+        assert_eq!(fields(&broker, "sequence"), [[1], [2]]);
+        assert_eq!(fields(&broker, "id"), [["d"], ["d"]]);
+    }
+
+    #[test]
+    fn publish_batch_sends_one_message() {
+        let (temperature, broker) = temperature();
+        let readings: Vec<_> = (20..23).map(|celsius| Temperature { celsius }).collect();
+        temperature.publish_batch(&readings).unwrap();
+        temperature.publish_batch(&[]).unwrap();
+        temperature.publish(&Temperature { celsius: 23 }).unwrap();
+
+        assert_eq!(fields(&broker, "celsius"), [vec![20, 21, 22], vec![23]]);
+        assert_eq!(fields(&broker, "sequence"), [vec![1, 2, 3], vec![4]]);
+
+        let timestamps = &fields(&broker, "timestamp")[0];
+        assert!(timestamps[0].is_u64());
+        assert_eq!(timestamps[0], timestamps[2]);
+    }
+
+    #[cfg(feature = "schema")]
+    #[test]
+    fn schema_includes_fields_added_by_sdk() {
+        let (temperature, _broker) = temperature();
+        let schema = serde_json::to_value(temperature.schema()).unwrap();
+
+        let mut required: Vec<_> = schema["required"].as_array().unwrap().clone();
+        required.sort_by_key(|field| field.to_string());
+        assert_eq!(
+            required,
+            ["celsius", "id", "sequence", "timestamp"].map(Value::from)
+        );
+
+        let properties = &schema["properties"];
+        assert_eq!(properties["id"]["type"], "string");
+        assert_eq!(properties["sequence"]["format"], "uint32");
+        assert_eq!(properties["timestamp"]["format"], "uint64");
+    }
+}
@@ -0,0 +1,253 @@
+use std::{
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+
+use super::{QoS, Transport, TransportEvent};
+use crate::{error::Result, ByteBeamError};
+
+/// Message recorded by [`MockTransport`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PublishedMessage {
+    pub topic: String,
+    pub qos: QoS,
+    pub payload: Vec<u8>,
+}
+
+#[derive(Default)]
+struct State {
+    published: Vec<PublishedMessage>,
+    subscriptions: Vec<String>,
+    message_id: u32,
+    reconnect_attempts: Vec<Instant>,
+    failing_reconnects: usize,
+    failing_publishes: usize,
+}
+
+/// In-memory transport, meant for running [`ByteBeamClient`](crate::ByteBeamClient) in host tests
+///
+/// Everything published through it is recorded, and incoming events are injected with the
+/// [`MockBroker`] handle returned alongside it. Publishing and reconnecting succeed immediately,
+/// unless [`MockBroker::fail_publishes`] or [`MockBroker::fail_reconnects`] say otherwise.
+///
+/// # Example
+/// ```no_run
+/// use bytebeam_esp_rs::{transport::MockTransport, ByteBeamClient};
+///
+/// let (transport, events, broker) = MockTransport::new();
+/// let client = ByteBeamClient::with_transport("device".into(), "project".into(), transport, events)?;
+///
+/// broker.connect();
+/// broker.send(
+///     "/tenants/project/devices/device/actions",
+///     r#"{"id": "1", "kind": "process", "name": "toggle", "payload": null}"#,
+/// );
+/// # anyhow::Ok(())
+/// ```
+pub struct MockTransport {
+    state: Arc<Mutex<State>>,
+    tx: Sender<TransportEvent>,
+}
+
+impl MockTransport {
+    /// Create a transport with its event stream and a handle to drive it
+    pub fn new() -> (Self, Receiver<TransportEvent>, MockBroker) {
+        let (tx, rx) = mpsc::channel();
+        let state = Arc::new(Mutex::new(State::default()));
+        let broker = MockBroker {
+            state: state.clone(),
+            tx: tx.clone(),
+        };
+
+        (MockTransport { state, tx }, rx, broker)
+    }
+}
+
+impl Transport for MockTransport {
+    fn publish(&mut self, topic: &str, qos: QoS, payload: &[u8]) -> Result<u32> {
+        let mut state = self.state.lock().unwrap();
+        if state.failing_publishes > 0 {
+            state.failing_publishes -= 1;
+            return Err(ByteBeamError::QueueFull);
+        }
+        state.message_id += 1;
+        state.published.push(PublishedMessage {
+            topic: topic.into(),
+            qos,
+            payload: payload.to_vec(),
+        });
+
+        Ok(state.message_id)
+    }
+
+    fn subscribe(&mut self, topic: &str, _qos: QoS) -> Result<u32> {
+        let mut state = self.state.lock().unwrap();
+        state.message_id += 1;
+        if !state.subscriptions.iter().any(|t| t == topic) {
+            state.subscriptions.push(topic.into());
+        }
+
+        Ok(state.message_id)
+    }
+
+    fn reconnect(&mut self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.reconnect_attempts.push(Instant::now());
+        // like a real broker, refusal is reported through events
+        let event = if state.failing_reconnects > 0 {
+            state.failing_reconnects -= 1;
+            TransportEvent::Disconnected
+        } else {
+            TransportEvent::Connected
+        };
+        self.tx.send(event).ok();
+
+        Ok(())
+    }
+}
+
+/// Broker side of a [`MockTransport`]
+#[derive(Clone)]
+pub struct MockBroker {
+    state: Arc<Mutex<State>>,
+    tx: Sender<TransportEvent>,
+}
+
+impl MockBroker {
+    /// Notify client that connection is established
+    pub fn connect(&self) {
+        self.tx.send(TransportEvent::Connected).ok();
+    }
+
+    /// Notify client that connection is lost
+    pub fn disconnect(&self) {
+        self.state.lock().unwrap().subscriptions.clear();
+        self.tx.send(TransportEvent::Disconnected).ok();
+    }
+
+    /// Deliver `payload` to client as if it was received on `topic`
+    pub fn send(&self, topic: &str, payload: impl Into<Vec<u8>>) {
+        self.tx
+            .send(TransportEvent::Received {
+                topic: topic.into(),
+                payload: payload.into(),
+            })
+            .ok();
+    }
+
+    /// Messages published by client so far
+    pub fn published(&self) -> Vec<PublishedMessage> {
+        self.state.lock().unwrap().published.clone()
+    }
+
+    /// Remove and return messages published by client so far
+    pub fn take_published(&self) -> Vec<PublishedMessage> {
+        std::mem::take(&mut self.state.lock().unwrap().published)
+    }
+
+    /// Make next `count` reconnection attempts fail
+    pub fn fail_reconnects(&self, count: usize) {
+        self.state.lock().unwrap().failing_reconnects = count;
+    }
+
+    /// Make next `count` publishes fail, as if outgoing queue of MQTT client was full
+    pub fn fail_publishes(&self, count: usize) {
+        self.state.lock().unwrap().failing_publishes = count;
+    }
+
+    /// Times at which client tried to reconnect so far
+    pub fn reconnect_attempts(&self) -> Vec<Instant> {
+        self.state.lock().unwrap().reconnect_attempts.clone()
+    }
+
+    /// Topics client is currently subscribed to
+    pub fn subscriptions(&self) -> Vec<String> {
+        self.state.lock().unwrap().subscriptions.clone()
+    }
+}
+
+/// Helpers for driving a client over [`MockTransport`] in unit tests
+#[cfg(test)]
+pub(crate) mod testing {
+    use std::{
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use super::MockBroker;
+    use crate::config::DeviceConfig;
+
+    /// Topic on which actions for [`device_config`] are received
+    pub(crate) const ACTIONS_TOPIC: &str = "/tenants/p/devices/d/actions";
+    /// Topic to which statuses of actions for [`device_config`] are published
+    pub(crate) const ACTION_STATUS_TOPIC: &str = "/tenants/p/devices/d/action/status";
+
+    /// Config of device `d` in project `p`
+    pub(crate) fn device_config() -> DeviceConfig {
+        DeviceConfig::from_slice(
+            br#"{
+                "project_id": "p",
+                "device_id": "d",
+                "broker": "localhost",
+                "port": 1883,
+                "authentication": {
+                    "ca_certificate": "",
+                    "device_certificate": "",
+                    "device_private_key": ""
+                }
+            }"#,
+        )
+        .unwrap()
+    }
+
+    /// Poll `condition` till it holds, panicking if it doesn't within a few seconds
+    pub(crate) fn wait_for(mut condition: impl FnMut() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !condition() {
+            assert!(Instant::now() < deadline, "condition wasn't met in time");
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    /// Deliver action `name` with id `id` to client
+    pub(crate) fn send_action(broker: &MockBroker, id: &str, name: &str, payload: &str) {
+        let action = serde_json::json!({
+            "id": id,
+            "kind": "process",
+            "name": name,
+            "payload": payload,
+        });
+        broker.send(ACTIONS_TOPIC, action.to_string());
+    }
+
+    /// States reported for action `id` so far, in order
+    pub(crate) fn action_states(broker: &MockBroker, id: &str) -> Vec<String> {
+        broker
+            .published()
+            .into_iter()
+            .filter(|message| message.topic == ACTION_STATUS_TOPIC)
+            .flat_map(|message| {
+                serde_json::from_slice::<Vec<serde_json::Value>>(&message.payload).unwrap()
+            })
+            .filter(|status| status["id"] == id)
+            .map(|status| status["state"].as_str().unwrap().to_owned())
+            .collect()
+    }
+
+    /// Records of each message published to `stream` of [`device_config`] so far, in order
+    pub(crate) fn stream_messages(
+        broker: &MockBroker,
+        stream: &str,
+    ) -> Vec<Vec<serde_json::Value>> {
+        let topic = crate::protocol::stream_topic("p", "d", stream);
+        broker
+            .published()
+            .into_iter()
+            .filter(|message| message.topic == topic)
+            .map(|message| serde_json::from_slice(&message.payload).unwrap())
+            .collect()
+    }
+}
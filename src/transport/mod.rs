@@ -0,0 +1,83 @@
+//! MQTT transport used by [`ByteBeamClient`](crate::ByteBeamClient)
+//!
+//! [`ByteBeamClient`](crate::ByteBeamClient) doesn't talk to a MQTT client directly, it goes through
+//! the [`Transport`] trait for outgoing messages and reads incoming ones from an [`EventStream`].
+//! This lets the same client run against the ESP-IDF MQTT client on the board, [rumqttc](https://docs.rs/rumqttc) on a
+//! host, or against [`MockTransport`] in tests.
+use std::sync::mpsc::Receiver;
+
+use crate::{error::Result, ByteBeamError};
+
+mod mock;
+
+#[cfg(feature = "esp-idf")]
+pub use crate::esp::EspMqttTransport;
+#[cfg(all(feature = "std", not(feature = "esp-idf")))]
+pub use crate::host::{HostMqttEvents, HostMqttTransport};
+#[cfg(test)]
+pub(crate) use mock::testing;
+pub use mock::{MockBroker, MockTransport, PublishedMessage};
+
+/// Transport used by [`ByteBeamClient`](crate::ByteBeamClient) when none is specified
+#[cfg(feature = "esp-idf")]
+pub type DefaultTransport = EspMqttTransport;
+/// Transport used by [`ByteBeamClient`](crate::ByteBeamClient) when none is specified
+#[cfg(all(feature = "std", not(feature = "esp-idf")))]
+pub type DefaultTransport = HostMqttTransport;
+/// Transport used by [`ByteBeamClient`](crate::ByteBeamClient) when none is specified
+#[cfg(not(any(feature = "esp-idf", feature = "std")))]
+pub type DefaultTransport = MockTransport;
+
+/// Quality of service for publishing and subscribing
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QoS {
+    AtMostOnce = 0,
+    AtLeastOnce = 1,
+    ExactlyOnce = 2,
+}
+
+/// Events coming from the broker
+#[derive(Debug)]
+pub enum TransportEvent {
+    /// Connection with broker is established
+    Connected,
+    /// Connection with broker is lost, or an attempt to connect failed
+    Disconnected,
+    /// Connecting with broker failed, or an established connection broke
+    Error(ByteBeamError),
+    /// A complete message was received on `topic`
+    Received { topic: String, payload: Vec<u8> },
+}
+
+/// Outgoing half of a MQTT connection
+pub trait Transport: Send + 'static {
+    /// Publish `payload` to `topic`, returning the message id
+    ///
+    /// Fails with [`ByteBeamError::QueueFull`] if message couldn't be queued for sending
+    fn publish(&mut self, topic: &str, qos: QoS, payload: &[u8]) -> Result<u32>;
+
+    /// Subscribe to `topic`, returning the message id
+    fn subscribe(&mut self, topic: &str, qos: QoS) -> Result<u32>;
+
+    /// Start re-establishing connection after [`TransportEvent::Disconnected`]
+    ///
+    /// Called by client once backoff delay has passed, outcome is reported through the
+    /// [`EventStream`]. Default does nothing, for transports which reconnect by themselves.
+    fn reconnect(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Incoming half of a MQTT connection
+pub trait EventStream: Send + 'static {
+    /// Block till next event is available
+    ///
+    /// Returns `None` once the connection is closed for good
+    fn next_event(&mut self) -> Option<TransportEvent>;
+}
+
+impl EventStream for Receiver<TransportEvent> {
+    fn next_event(&mut self) -> Option<TransportEvent> {
+        self.recv().ok()
+    }
+}